@@ -8,7 +8,7 @@ Provides REST API for movie search and streaming URL generation
 import eventlet
 eventlet.monkey_patch()
 
-from flask import Flask, request, jsonify, render_template_string, send_file, Response, send_from_directory
+from flask import Flask, request, jsonify, render_template_string, send_file, Response, send_from_directory, g
 from flask_cors import CORS
 from flask_socketio import SocketIO, emit, join_room, leave_room, rooms
 import requests
@@ -16,20 +16,37 @@ import sys
 import os
 import base64
 import gzip
-from io import BytesIO
+from io import BytesIO, StringIO
+from collections import OrderedDict
 import random
 import string
 from datetime import datetime, timedelta
 import json
 import atexit
+import csv
 import re
+import math
 import subprocess
 import time
+import functools
+import hashlib
+import hmac
+import struct
+import secrets
+import smtplib
+import threading
+import warnings
+import zipfile
+from email.message import EmailMessage
 from urllib.parse import urlparse, parse_qs, quote
-from flask_jwt_extended import JWTManager, create_access_token, jwt_required, get_jwt_identity
-from pymongo import MongoClient
+from flask_jwt_extended import JWTManager, create_access_token, create_refresh_token, jwt_required, get_jwt_identity, get_jwt, get_jti, verify_jwt_in_request, decode_token
+from pymongo import MongoClient, ReturnDocument
+from pymongo.errors import DuplicateKeyError
 from bson.objectid import ObjectId
 from werkzeug.security import generate_password_hash, check_password_hash
+from werkzeug.middleware.proxy_fix import ProxyFix
+from werkzeug.exceptions import RequestEntityTooLarge
+from PIL import Image, ImageOps, UnidentifiedImageError
 from dotenv import load_dotenv
 
 # Load environment variables
@@ -43,15 +60,42 @@ VIDSRC_SCRAPER_URL = os.environ.get('VIDSRC_SCRAPER_URL', 'http://localhost:4000
 OMDB_API_KEY = os.environ.get('OMDB_API_KEY', 'ecbf499d')
 
 app = Flask(__name__)
+# Number of reverse proxies in front of the app. Only then is X-Forwarded-For trusted, and only the
+# entries those proxies added - anything further left was written by the client.
+TRUSTED_PROXY_COUNT = int(os.environ.get('TRUSTED_PROXY_COUNT', 0))
+if TRUSTED_PROXY_COUNT > 0:
+    app.wsgi_app = ProxyFix(app.wsgi_app, x_for=TRUSTED_PROXY_COUNT)
 # Generate a secure random secret key for production
 app.config['SECRET_KEY'] = os.environ.get('SECRET_KEY', os.urandom(24).hex())
 app.config['JWT_SECRET_KEY'] = os.environ.get('JWT_SECRET_KEY', 'your-secret-key-change-this-in-production')
-app.config['JWT_ACCESS_TOKEN_EXPIRES'] = timedelta(days=7)
+# Short-lived access tokens; clients renew them through /api/auth/refresh
+app.config['JWT_ACCESS_TOKEN_EXPIRES'] = timedelta(minutes=int(os.environ.get('JWT_ACCESS_TOKEN_MINUTES', 15)))
+app.config['JWT_REFRESH_TOKEN_EXPIRES'] = timedelta(days=int(os.environ.get('JWT_REFRESH_TOKEN_DAYS', 30)))
+# Largest request body accepted at all (list imports are the biggest uploads); bigger ones are refused before they're read
+app.config['MAX_CONTENT_LENGTH'] = int(os.environ.get('MAX_REQUEST_MB', 11)) * 1024 * 1024
+
+# Rate limits as "<requests>/<seconds>" per IP or per account
+app.config['RATE_LIMIT_BACKEND'] = os.environ.get('RATE_LIMIT_BACKEND', 'memory')
+app.config['RATE_LIMITS'] = {
+    'login_ip': os.environ.get('RATE_LIMIT_LOGIN_IP', '20/300'),
+    'login_account': os.environ.get('RATE_LIMIT_LOGIN_ACCOUNT', '10/300'),
+    'register_ip': os.environ.get('RATE_LIMIT_REGISTER_IP', '5/3600'),
+    'friend_search_ip': os.environ.get('RATE_LIMIT_FRIEND_SEARCH_IP', '60/60'),
+    'friend_search_account': os.environ.get('RATE_LIMIT_FRIEND_SEARCH_ACCOUNT', '30/60')
+}
+app.config['LOGIN_LOCKOUT_THRESHOLD'] = int(os.environ.get('LOGIN_LOCKOUT_THRESHOLD', 5))
+app.config['LOGIN_LOCKOUT_BASE_SECONDS'] = int(os.environ.get('LOGIN_LOCKOUT_BASE_SECONDS', 60))
+app.config['LOGIN_LOCKOUT_MAX_SECONDS'] = int(os.environ.get('LOGIN_LOCKOUT_MAX_SECONDS', 3600))
 
 CORS(app)  # Enable CORS for frontend access
 socketio = SocketIO(app, cors_allowed_origins="*", async_mode='eventlet')
 jwt = JWTManager(app)
 
+
+@app.errorhandler(RequestEntityTooLarge)
+def request_too_large(e):
+    return jsonify({'error': f"Request body is larger than {app.config['MAX_CONTENT_LENGTH'] // (1024 * 1024)} MB"}), 413
+
 # MongoDB connection
 mongo_uri = os.environ.get('MONGO_URI', 'mongodb://localhost:27017/')
 client = MongoClient(mongo_uri)
@@ -63,25 +107,103 @@ comments_collection = db['comments']
 comment_likes_collection = db['comment_likes']
 friend_requests_collection = db['friend_requests']
 ratings_collection = db['ratings']
+sessions_collection = db['sessions']
+token_blocklist_collection = db['token_blocklist']
+email_tokens_collection = db['email_tokens']
+rate_limits_collection = db['rate_limits']
+admin_audit_log_collection = db['admin_audit_log']
+comment_revisions_collection = db['comment_revisions']
+comment_reports_collection = db['comment_reports']
+user_warnings_collection = db['user_warnings']
+comment_reactions_collection = db['comment_reactions']
+notifications_collection = db['notifications']
+review_helpful_collection = db['review_helpful']
+lists_collection = db['lists']
+watch_history_collection = db['watch_history']
+show_tracking_collection = db['show_tracking']
+list_imports_collection = db['list_imports']
 
 # Create indexes
 try:
     users_collection.create_index('username', unique=True)
     users_collection.create_index('email', unique=True)
+    users_collection.create_index('blocked_users')
+    users_collection.create_index('calendar_token')
     comments_collection.create_index([('content_id', 1), ('user_id', 1)])
+    comments_collection.create_index([('content_id', 1), ('parent_comment_id', 1), ('created_at', -1)])
+    comments_collection.create_index([('thread_root_id', 1), ('created_at', 1)])
     comment_likes_collection.create_index([('comment_id', 1), ('user_id', 1)], unique=True)
+    # Friendships are accepted friend_requests, looked up from either side
+    friend_requests_collection.create_index([('from_user_id', 1), ('status', 1), ('to_user_id', 1)])
+    friend_requests_collection.create_index([('to_user_id', 1), ('status', 1), ('from_user_id', 1)])
     ratings_collection.create_index([('content_key', 1), ('user_id', 1)], unique=True)
     ratings_collection.create_index([('content_key', 1)])
+    ratings_collection.create_index([('tv_id', 1), ('season', 1), ('episode', 1)])
+    ratings_collection.create_index([('user_id', 1), ('review.created_at', -1)])
+    review_helpful_collection.create_index([('review_id', 1), ('user_id', 1)], unique=True)
+    lists_collection.create_index([('owner_id', 1), ('name_key', 1)], unique=True)
+    lists_collection.create_index('collaborators')
+    lists_collection.create_index('share_token')
+    lists_collection.create_index('items.added_by')
+    watch_history_collection.create_index([('user_id', 1), ('content_key', 1)], unique=True)
+    watch_history_collection.create_index([('user_id', 1), ('last_watched_at', -1)])
+    watch_history_collection.create_index([('user_id', 1), ('content_type', 1), ('content_id', 1), ('season', 1), ('episode', 1)])
+    show_tracking_collection.create_index([('user_id', 1), ('tv_id', 1)], unique=True)
+    list_imports_collection.create_index([('user_id', 1), ('status', 1)])
+    list_imports_collection.create_index('created_at', expireAfterSeconds=7 * 24 * 3600)  # reports are kept for a week
+    sessions_collection.create_index([('user_id', 1), ('revoked', 1)])
+    token_blocklist_collection.create_index('jti', unique=True)
+    token_blocklist_collection.create_index('expires_at', expireAfterSeconds=0)  # Mongo TTL purges expired entries
+    email_tokens_collection.create_index('token_hash', unique=True)
+    email_tokens_collection.create_index('expires_at', expireAfterSeconds=0)
+    rate_limits_collection.create_index('expires_at', expireAfterSeconds=0)
+    admin_audit_log_collection.create_index([('created_at', -1)])
+    admin_audit_log_collection.create_index([('target_user_id', 1), ('created_at', -1)])
+    comment_revisions_collection.create_index([('comment_id', 1), ('replaced_at', -1)])
+    comment_reports_collection.create_index([('comment_id', 1), ('reporter_id', 1)], unique=True)
+    comment_reports_collection.create_index([('status', 1), ('created_at', -1)])
+    user_warnings_collection.create_index([('user_id', 1), ('created_at', -1)])
+    comment_reactions_collection.create_index([('comment_id', 1), ('user_id', 1), ('reaction', 1)], unique=True)
+    notifications_collection.create_index([('user_id', 1), ('read', 1), ('created_at', -1)])
+    notifications_collection.create_index([('user_id', 1), ('type', 1), ('actor_id', 1), ('comment_id', 1)])
 except Exception as e:
     print(f"Note: Some indexes may already exist: {e}")
 
+# Bootstrap admins, promoted on startup: accounts listed by id in ADMIN_USER_IDS, and accounts
+# listed in ADMIN_USERNAMES once their email is verified (so registering a listed name isn't enough)
+try:
+    bootstrap_ids = [ObjectId(u.strip()) for u in os.environ.get('ADMIN_USER_IDS', '').split(',') if u.strip()]
+    bootstrap_admins = [u.strip() for u in os.environ.get('ADMIN_USERNAMES', '').split(',') if u.strip()]
+    if bootstrap_ids:
+        users_collection.update_many({'_id': {'$in': bootstrap_ids}}, {'$set': {'role': 'admin'}})
+    if bootstrap_admins:
+        users_collection.update_many(
+            {'username': {'$in': bootstrap_admins}, 'email_verified': True},
+            {'$set': {'role': 'admin'}}
+        )
+except Exception as e:
+    print(f"Note: Could not promote bootstrap admins: {e}")
+
 # Cache file paths
 CACHE_DIR = os.path.join(os.path.dirname(__file__), 'cache')
 SEASON_CACHE_FILE = os.path.join(CACHE_DIR, 'season_cache.json')
 OMDB_CACHE_FILE = os.path.join(CACHE_DIR, 'omdb_cache.json')
+TITLE_CACHE_FILE = os.path.join(CACHE_DIR, 'title_cache.json')
+
+# Uploaded avatars (resized copies only)
+AVATAR_DIR = os.path.join(os.path.dirname(__file__), 'uploads', 'avatars')
+AVATAR_SIZE = 256
+AVATAR_MAX_BYTES = 5 * 1024 * 1024
+AVATAR_MAX_PIXELS = 4096 * 4096  # a small file can still declare huge dimensions
+AVATAR_FORMATS = ('PNG', 'JPEG', 'GIF', 'WEBP')
+
+# Images over the pixel limit fail in Image.open(), before anything is decoded
+Image.MAX_IMAGE_PIXELS = AVATAR_MAX_PIXELS
+warnings.simplefilter('error', Image.DecompressionBombWarning)
 
-# Ensure cache directory exists
+# Ensure cache and upload directories exist
 os.makedirs(CACHE_DIR, exist_ok=True)
+os.makedirs(AVATAR_DIR, exist_ok=True)
 
 # Load caches from disk
 def load_cache(cache_file):
@@ -103,6 +225,8 @@ def save_cache(cache_file, cache_data):
 # Caching
 season_cache = load_cache(SEASON_CACHE_FILE)  # {tv_id_season_number: season_data}
 omdb_cache = load_cache(OMDB_CACHE_FILE)  # {imdb_id: omdb_data}
+title_cache = load_cache(TITLE_CACHE_FILE)  # {"movie:123": {title, poster_path, year, imdb_id}}
+schedule_cache = OrderedDict()  # {"tv:123" / "tv:123:s2": (fetched_at, data)} - air dates change, so memory only, least recently used first
 
 print(f"Loaded {len(season_cache)} season(s) and {len(omdb_cache)} OMDB entries from cache")
 
@@ -110,6 +234,7 @@ print(f"Loaded {len(season_cache)} season(s) and {len(omdb_cache)} OMDB entries
 def save_all_caches():
     save_cache(SEASON_CACHE_FILE, season_cache)
     save_cache(OMDB_CACHE_FILE, omdb_cache)
+    save_cache(TITLE_CACHE_FILE, title_cache)
     print(f"Saved {len(season_cache)} season(s) and {len(omdb_cache)} OMDB entries to cache")
 
 atexit.register(save_all_caches)
@@ -124,6 +249,7 @@ class TMDBService:
         self.base_url = "https://api.themoviedb.org/3"
         self.api_key = TMDB_API_KEY
         self.image_base_url = "https://image.tmdb.org/t/p/w500"
+        self.timeout = int(os.environ.get('TMDB_TIMEOUT', 10))
 
     def search_movies(self, query, year=None, page=1):
         """Search for movies"""
@@ -137,7 +263,7 @@ class TMDBService:
             params["year"] = year
 
         try:
-            response = requests.get(f"{self.base_url}/search/movie", params=params)
+            response = requests.get(f"{self.base_url}/search/movie", params=params, timeout=self.timeout)
             response.raise_for_status()
             data = response.json()
 
@@ -165,7 +291,7 @@ class TMDBService:
             params["first_air_date_year"] = year
 
         try:
-            response = requests.get(f"{self.base_url}/search/tv", params=params)
+            response = requests.get(f"{self.base_url}/search/tv", params=params, timeout=self.timeout)
             response.raise_for_status()
             data = response.json()
 
@@ -220,7 +346,7 @@ class TMDBService:
         """Get detailed movie information"""
         try:
             params = {"api_key": self.api_key, "append_to_response": "external_ids"}
-            response = requests.get(f"{self.base_url}/movie/{movie_id}", params=params)
+            response = requests.get(f"{self.base_url}/movie/{movie_id}", params=params, timeout=self.timeout)
             response.raise_for_status()
             movie = response.json()
 
@@ -239,7 +365,7 @@ class TMDBService:
         """Get detailed TV show information"""
         try:
             params = {"api_key": self.api_key, "append_to_response": "external_ids"}
-            response = requests.get(f"{self.base_url}/tv/{tv_id}", params=params)
+            response = requests.get(f"{self.base_url}/tv/{tv_id}", params=params, timeout=self.timeout)
             response.raise_for_status()
             show = response.json()
 
@@ -254,6 +380,17 @@ class TMDBService:
         except Exception as e:
             return {"error": str(e)}
 
+    def find_by_imdb_id(self, imdb_id):
+        """Look up movies and shows by IMDb id (tt...)"""
+        try:
+            params = {"api_key": self.api_key, "external_source": "imdb_id"}
+            response = requests.get(f"{self.base_url}/find/{imdb_id}", params=params, timeout=self.timeout)
+            response.raise_for_status()
+            return response.json()
+        except Exception as e:
+            print(f"Error finding IMDb id {imdb_id}: {e}")
+            return {}
+
     def get_tv_episode_external_ids(self, tv_id, season_number, episode_number):
         """Get external IDs for a specific episode"""
         try:
@@ -280,7 +417,7 @@ class TMDBService:
             print(f"Fetching season {cache_key} from API (not in cache or quick mode)")
 
             params = {"api_key": self.api_key}
-            response = requests.get(f"{self.base_url}/tv/{tv_id}/season/{season_number}", params=params)
+            response = requests.get(f"{self.base_url}/tv/{tv_id}/season/{season_number}", params=params, timeout=self.timeout)
             response.raise_for_status()
             season = response.json()
 
@@ -732,6 +869,52 @@ class TMDBService:
 
 tmdb = TMDBService()
 
+
+def get_content_summary(content_type, content_id):
+    """Title and poster for a movie or show, cached - for places that only stored the TMDB id"""
+    key = f"{content_type}:{content_id}"
+    if key not in title_cache:
+        details = tmdb.get_tv_details(content_id) if content_type == 'tv' else tmdb.get_movie_details(content_id)
+        if details.get('error'):
+            return {'title': None, 'poster_path': None}
+        title_cache[key] = {
+            'title': details.get('name') if content_type == 'tv' else details.get('title'),
+            'poster_path': details.get('poster_path')
+        }
+    return {k: title_cache[key].get(k) for k in ('title', 'poster_path')}
+
+
+# Lookups that failed aren't retried for a while, so a TMDB outage doesn't cost a request per title every time
+EXTERNAL_IDS_RETRY_SECONDS = 10 * 60
+external_ids_failed = {}  # {"movie:123": failed_at}
+
+
+def external_ids_cached(content_type, content_id):
+    """Whether get_content_external_ids can answer without calling TMDB"""
+    key = f"{content_type}:{content_id}"
+    return 'imdb_id' in title_cache.get(key, {}) or time.time() - external_ids_failed.get(key, 0) < EXTERNAL_IDS_RETRY_SECONDS
+
+
+def get_content_external_ids(content_type, content_id):
+    """Release year and IMDb id for a movie or show, cached with its title"""
+    key = f"{content_type}:{content_id}"
+    if 'imdb_id' not in title_cache.get(key, {}):
+        if external_ids_cached(content_type, content_id):
+            return {'year': None, 'imdb_id': None}  # failed recently
+        details = tmdb.get_tv_details(content_id) if content_type == 'tv' else tmdb.get_movie_details(content_id)
+        if details.get('error'):
+            external_ids_failed[key] = time.time()
+            return {'year': None, 'imdb_id': None}
+        external_ids_failed.pop(key, None)
+        released = details.get('first_air_date' if content_type == 'tv' else 'release_date') or ''
+        title_cache[key] = {
+            'title': details.get('name') if content_type == 'tv' else details.get('title'),
+            'poster_path': details.get('poster_path'),
+            'year': int(released[:4]) if released[:4].isdigit() else None,
+            'imdb_id': (details.get('external_ids') or {}).get('imdb_id')
+        }
+    return {k: title_cache[key].get(k) for k in ('year', 'imdb_id')}
+
 class SubtitleService:
     def __init__(self):
         self.user_agent = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36"
@@ -889,6 +1072,76 @@ class SubtitleService:
 
 subtitle_service = SubtitleService()
 
+
+class MailSender:
+    """Base mail transport - subclasses deliver a plain-text message"""
+    def __init__(self):
+        self.from_address = os.environ.get('MAIL_FROM', 'GlitchBox <no-reply@glitchbox.local>')
+
+    def build_message(self, to, subject, body):
+        message = EmailMessage()
+        message['From'] = self.from_address
+        message['To'] = to
+        message['Subject'] = subject
+        message.set_content(body)
+        return message
+
+    def send(self, to, subject, body):
+        raise NotImplementedError
+
+
+class SMTPMailSender(MailSender):
+    """Deliver through an SMTP relay (MAIL_SMTP_HOST / PORT / USERNAME / PASSWORD / USE_TLS)"""
+    def __init__(self):
+        super().__init__()
+        self.host = os.environ.get('MAIL_SMTP_HOST', 'localhost')
+        self.port = int(os.environ.get('MAIL_SMTP_PORT', 587))
+        self.username = os.environ.get('MAIL_SMTP_USERNAME')
+        self.password = os.environ.get('MAIL_SMTP_PASSWORD')
+        self.use_tls = os.environ.get('MAIL_SMTP_USE_TLS', 'true').lower() == 'true'
+
+    def send(self, to, subject, body):
+        message = self.build_message(to, subject, body)
+        with smtplib.SMTP(self.host, self.port, timeout=10) as smtp:
+            if self.use_tls:
+                smtp.starttls()
+            if self.username:
+                smtp.login(self.username, self.password)
+            smtp.send_message(message)
+
+
+class FileMailSender(MailSender):
+    """Write each message to an .eml file in MAIL_OUTBOX_DIR - handy for local testing"""
+    def __init__(self):
+        super().__init__()
+        self.outbox_dir = os.environ.get('MAIL_OUTBOX_DIR') or os.path.join(CACHE_DIR, 'outbox')
+        os.makedirs(self.outbox_dir, exist_ok=True)
+
+    def send(self, to, subject, body):
+        message = self.build_message(to, subject, body)
+        filename = f"{datetime.utcnow().strftime('%Y%m%d-%H%M%S')}-{secrets.token_hex(4)}.eml"
+        with open(os.path.join(self.outbox_dir, filename), 'w') as f:
+            f.write(message.as_string())
+        print(f"[Mail] Wrote '{subject}' for {to} to {filename}")
+
+
+class ConsoleMailSender(MailSender):
+    """Print messages to stdout instead of sending them"""
+    def send(self, to, subject, body):
+        print(f"[Mail] To: {to}\n[Mail] Subject: {subject}\n{body}")
+
+
+def create_mail_sender():
+    """Pick the mail transport from MAIL_BACKEND (smtp, file or console)"""
+    backend = os.environ.get('MAIL_BACKEND', 'console').lower()
+    if backend == 'smtp':
+        return SMTPMailSender()
+    if backend == 'file':
+        return FileMailSender()
+    return ConsoleMailSender()
+
+mail_sender = create_mail_sender()
+
 @app.route('/')
 def index():
     """Serve the main movie streaming interface"""
@@ -1083,11 +1336,673 @@ def api_omdb_lookup(imdb_id):
 #     )
 #     return jsonify({'ok': True, 'rating': doc}), 200
 
+# ============= REQUEST VALIDATION =============
+
+USERNAME_PATTERN = re.compile(r'^[A-Za-z0-9_.-]{3,30}$')
+EMAIL_PATTERN = re.compile(r'^[^@\s]+@[^@\s]+\.[^@\s]+$')
+PASSWORD_MIN_LENGTH = int(os.environ.get('PASSWORD_MIN_LENGTH', 8))
+COMMON_PASSWORDS = {
+    'password', 'password1', 'password123', '12345678', '123456789', '1234567890',
+    'qwerty123', 'iloveyou', 'letmein1', 'welcome1', 'abc12345', 'passw0rd'
+}
+
+
+class Field:
+    """
+    One field of a JSON request schema.
+    types: a type or tuple of types the value must be (bool never counts as int/float)
+    """
+    def __init__(self, types, required=True, nullable=False, min_length=None, max_length=None,
+                 pattern=None, pattern_message=None, choices=None, min_value=None, max_value=None, check=None):
+        self.types = types if isinstance(types, tuple) else (types,)
+        self.required = required
+        self.nullable = nullable
+        self.min_length = min_length
+        self.max_length = max_length
+        self.pattern = pattern
+        self.pattern_message = pattern_message
+        self.choices = choices
+        self.min_value = min_value
+        self.max_value = max_value
+        self.check = check
+
+    def validate(self, value):
+        """Return a list of error messages for this value (empty if valid)"""
+        if value is None:
+            return [] if self.nullable else ['is required']
+
+        if isinstance(value, bool) and bool not in self.types:
+            return [f"must be of type {' or '.join(t.__name__ for t in self.types)}"]
+        if not isinstance(value, self.types):
+            return [f"must be of type {' or '.join(t.__name__ for t in self.types)}"]
+
+        errors = []
+        if isinstance(value, str) and self.min_length and not value.strip():
+            return ['must not be blank']
+        if isinstance(value, (str, list)):
+            if self.min_length is not None and len(value) < self.min_length:
+                errors.append(f'must be at least {self.min_length} characters' if isinstance(value, str)
+                              else f'must contain at least {self.min_length} items')
+            if self.max_length is not None and len(value) > self.max_length:
+                errors.append(f'must be at most {self.max_length} characters' if isinstance(value, str)
+                              else f'must contain at most {self.max_length} items')
+        if isinstance(value, str):
+            if self.pattern and not self.pattern.match(value):
+                errors.append(self.pattern_message or 'has an invalid format')
+        if isinstance(value, (int, float)):
+            if self.min_value is not None and value < self.min_value:
+                errors.append(f'must be at least {self.min_value}')
+            if self.max_value is not None and value > self.max_value:
+                errors.append(f'must be at most {self.max_value}')
+        if self.choices is not None and value not in self.choices:
+            errors.append(f"must be one of: {', '.join(str(c) for c in self.choices)}")
+        if not errors and self.check:
+            errors.extend(self.check(value))
+        return errors
+
+
+def _password_strength_errors(password, username=None):
+    """Minimum password policy shared by registration and password reset"""
+    errors = []
+    if len(password) < PASSWORD_MIN_LENGTH:
+        errors.append(f'must be at least {PASSWORD_MIN_LENGTH} characters')
+    if not re.search(r'[A-Za-z]', password) or not re.search(r'\d', password):
+        errors.append('must contain at least one letter and one number')
+    if password.lower() in COMMON_PASSWORDS:
+        errors.append('is too common')
+    if username and password.lower() == username.lower():
+        errors.append('must not be the same as the username')
+    return errors
+
+
+def _validation_error(errors):
+    """Structured 422 response: one entry per failing field"""
+    return jsonify({
+        'error': 'Validation failed',
+        'errors': [{'field': field, 'message': message} for field, message in errors]
+    }), 422
+
+
+def _schema_errors(schema, data):
+    """(field, message) pairs for a dict checked against a {field: Field} schema"""
+    errors = []
+    for name, field in schema.items():
+        value = data.get(name)
+        if value is None and not field.required:
+            continue
+        for message in field.validate(value):
+            errors.append((name, message))
+    return errors
+
+
+def validate_json(schema, check=None):
+    """
+    Validate the JSON body against a {field: Field} schema before the view runs.
+    check(data, failed_fields) may return extra (field, message) pairs for cross-field rules.
+    Unknown fields are ignored; handlers keep reading request.get_json().
+    """
+    def decorator(fn):
+        @functools.wraps(fn)
+        def wrapper(*args, **kwargs):
+            data = request.get_json(silent=True)
+            if not isinstance(data, dict):
+                return _validation_error([('body', 'must be a JSON object')])
+
+            errors = _schema_errors(schema, data)
+
+            if check:
+                failed = {name for name, _ in errors}
+                errors.extend(check(data, failed))
+
+            if errors:
+                return _validation_error(errors)
+            return fn(*args, **kwargs)
+        return wrapper
+    return decorator
+
+
+USERNAME_FIELD = Field(str, pattern=USERNAME_PATTERN,
+                       pattern_message='must be 3-30 characters of letters, numbers, dots, dashes or underscores')
+EMAIL_FIELD = Field(str, max_length=254, pattern=EMAIL_PATTERN, pattern_message='must be a valid email address')
+CONTENT_ID_FIELD = Field((int, str), check=lambda v: [] if str(v).strip() and len(str(v)) <= 64 else ['is invalid'])
+CONTENT_TYPE_FIELD = Field(str, choices=('movie', 'tv'))
+
+REGISTER_SCHEMA = {
+    'username': USERNAME_FIELD,
+    'email': EMAIL_FIELD,
+    'password': Field(str, max_length=128)
+}
+
+LOGIN_SCHEMA = {
+    'username': Field(str, min_length=1, max_length=64),
+    'password': Field(str, min_length=1, max_length=128)
+}
+
+TOKEN_SCHEMA = {
+    'token': Field(str, min_length=1, max_length=256)
+}
+
+FORGOT_PASSWORD_SCHEMA = {
+    'email': EMAIL_FIELD
+}
+
+RESET_PASSWORD_SCHEMA = {
+    'token': Field(str, min_length=1, max_length=256),
+    'password': Field(str, max_length=128)
+}
+
+# The rated title's name and poster are stored on the rating so review pages don't have to look them up
+RATED_CONTENT_FIELDS = {
+    'content_title': Field(str, required=False, nullable=True, max_length=300),
+    'poster_path': Field(str, required=False, nullable=True, max_length=500)
+}
+
+RATING_SCHEMA = {
+    'rating': Field((int, float), min_value=1, max_value=10),
+    **RATED_CONTENT_FIELDS
+}
+
+# A review rides on the reviewer's rating; rating may be sent along to set both at once
+REVIEW_SCHEMA = {
+    'title': Field(str, min_length=1, max_length=120),
+    'body': Field(str, min_length=1, max_length=int(os.environ.get('REVIEW_MAX_LENGTH', 5000))),
+    'spoiler': Field(bool, required=False, nullable=True),
+    'rating': Field((int, float), required=False, min_value=1, max_value=10),
+    **RATED_CONTENT_FIELDS
+}
+
+WATCHLIST_ITEM_SCHEMA = {
+    'content_id': CONTENT_ID_FIELD,
+    'content_type': CONTENT_TYPE_FIELD,
+    'title': Field(str, min_length=1, max_length=300),
+    'poster_path': Field(str, required=False, nullable=True, max_length=500),
+    'list_name': Field(str, required=False, min_length=1, max_length=50)
+}
+
+WATCHLIST_RENAME_SCHEMA = {
+    'old_name': Field(str, min_length=1, max_length=50),
+    'new_name': Field(str, min_length=1, max_length=50)
+}
+
+LIST_SCHEMA = {
+    'name': Field(str, min_length=1, max_length=50),
+    'description': Field(str, required=False, nullable=True, max_length=500),
+    'cover': Field(str, required=False, nullable=True, max_length=500),
+    'privacy': Field(str, required=False, choices=('private', 'friends', 'public'))
+}
+
+LIST_UPDATE_SCHEMA = {**LIST_SCHEMA, 'name': Field(str, required=False, min_length=1, max_length=50)}
+
+LIST_NOTE_FIELD = Field(str, required=False, nullable=True, max_length=1000)
+
+LIST_ITEM_SCHEMA = {
+    'content_id': CONTENT_ID_FIELD,
+    'content_type': CONTENT_TYPE_FIELD,
+    'title': Field(str, min_length=1, max_length=300),
+    'poster_path': Field(str, required=False, nullable=True, max_length=500),
+    'note': LIST_NOTE_FIELD
+}
+
+LIST_ITEM_NOTE_SCHEMA = {
+    'note': LIST_NOTE_FIELD
+}
+
+LIST_ORDER_SCHEMA = {
+    'item_ids': Field(list, max_length=5000,
+                      check=lambda ids: [] if all(isinstance(i, str) and ObjectId.is_valid(i) for i in ids) else ['must be item ids'])
+}
+
+CONTINUE_WATCHING_SCHEMA = {
+    'content_id': CONTENT_ID_FIELD,
+    'content_type': CONTENT_TYPE_FIELD,
+    'title': Field(str, min_length=1, max_length=300),
+    'poster_path': Field(str, required=False, nullable=True, max_length=500),
+    'progress': Field((int, float), required=False, nullable=True, min_value=0, max_value=100),
+    'season': Field(int, required=False, nullable=True, min_value=0),
+    'episode': Field(int, required=False, nullable=True, min_value=0)
+}
+
+WATCH_PROGRESS_SCHEMA = {
+    'content_id': CONTENT_ID_FIELD,
+    'content_type': CONTENT_TYPE_FIELD,
+    'title': Field(str, required=False, nullable=True, max_length=300),
+    'poster_path': Field(str, required=False, nullable=True, max_length=500),
+    'season': Field(int, required=False, nullable=True, min_value=0),
+    'episode': Field(int, required=False, nullable=True, min_value=0),
+    'progress_seconds': Field((int, float), required=False, nullable=True, min_value=0),
+    'duration_seconds': Field((int, float), required=False, nullable=True, min_value=1),
+    'completed': Field(bool, required=False, nullable=True)
+}
+
+FAVORITE_SCHEMA = {
+    'channel_id': Field(str, min_length=1, max_length=500),
+    'channel_name': Field(str, min_length=1, max_length=200)
+}
+
+FAVORITE_REMOVE_SCHEMA = {
+    'channel_id': Field(str, min_length=1, max_length=500)
+}
+
+FRIEND_REQUEST_SCHEMA = {
+    'username': Field(str, min_length=1, max_length=30)
+}
+
+COMMENT_TEXT_FIELD = Field(str, min_length=1, max_length=int(os.environ.get('COMMENT_MAX_LENGTH', 2000)))
+
+COMMENT_VISIBILITIES = ('friends', 'public')
+
+COMMENT_SCHEMA = {
+    'content_id': CONTENT_ID_FIELD,
+    'content_type': CONTENT_TYPE_FIELD,
+    'comment_text': COMMENT_TEXT_FIELD,
+    'parent_comment_id': Field(str, required=False, nullable=True,
+                               check=lambda v: [] if ObjectId.is_valid(v) else ['is not a valid id']),
+    'visibility': Field(str, required=False, nullable=True, choices=COMMENT_VISIBILITIES),
+    'spoiler': Field(bool, required=False, nullable=True),
+    'season': Field(int, required=False, nullable=True, min_value=0),
+    'episode': Field(int, required=False, nullable=True, min_value=1)
+}
+
+COMMENT_EDIT_SCHEMA = {
+    'comment_text': COMMENT_TEXT_FIELD,
+    'spoiler': Field(bool, required=False, nullable=True)
+}
+
+NOTIFICATIONS_READ_SCHEMA = {
+    'ids': Field(list, required=False, nullable=True, max_length=200,
+                 check=lambda ids: [] if all(isinstance(i, str) and ObjectId.is_valid(i) for i in ids)
+                 else ['must be a list of notification ids'])
+}
+
+REPORT_REASONS = ('spam', 'harassment', 'hate', 'spoiler', 'other')
+
+COMMENT_REPORT_SCHEMA = {
+    'reason': Field(str, choices=REPORT_REASONS),
+    'details': Field(str, required=False, nullable=True, max_length=500)
+}
+
+MODERATION_ACTION_SCHEMA = {
+    'reason': Field(str, required=False, nullable=True, max_length=500)
+}
+
+USER_WARNING_SCHEMA = {
+    'reason': Field(str, min_length=1, max_length=500),
+    'comment_id': Field(str, required=False, nullable=True,
+                        check=lambda v: [] if ObjectId.is_valid(v) else ['is not a valid id'])
+}
+
+TOTP_CODE_FIELD = Field(str, pattern=re.compile(r'^\d{6}$'), pattern_message='must be a 6-digit code')
+
+TWO_FACTOR_ENABLE_SCHEMA = {
+    'code': TOTP_CODE_FIELD
+}
+
+TWO_FACTOR_LOGIN_SCHEMA = {
+    'two_factor_token': Field(str, min_length=1, max_length=256),
+    'code': Field(str, required=False, pattern=TOTP_CODE_FIELD.pattern, pattern_message=TOTP_CODE_FIELD.pattern_message),
+    'recovery_code': Field(str, required=False, min_length=1, max_length=32)
+}
+
+TWO_FACTOR_DISABLE_SCHEMA = {
+    'password': Field(str, min_length=1, max_length=128),
+    'code': Field(str, required=False, pattern=TOTP_CODE_FIELD.pattern, pattern_message=TOTP_CODE_FIELD.pattern_message),
+    'recovery_code': Field(str, required=False, min_length=1, max_length=32)
+}
+
+ACCOUNT_DELETE_SCHEMA = {
+    'password': Field(str, min_length=1, max_length=128),
+    'code': Field(str, required=False, pattern=TOTP_CODE_FIELD.pattern, pattern_message=TOTP_CODE_FIELD.pattern_message),
+    'recovery_code': Field(str, required=False, min_length=1, max_length=32)
+}
+
+PROFILE_VISIBILITIES = ('public', 'friends', 'private')
+
+PROFILE_SCHEMA = {
+    'display_name': Field(str, required=False, nullable=True, max_length=50),
+    'bio': Field(str, required=False, nullable=True, max_length=500),
+    'favorite_genres': Field(list, required=False, max_length=10, check=lambda v: [] if all(
+        isinstance(g, str) and 0 < len(g) <= 40 for g in v) else ['must be a list of genre names']),
+    'visibility': Field(str, required=False, choices=PROFILE_VISIBILITIES)
+}
+
+ACTIVITY_SHARING_KEYS = ('watching', 'watchlist', 'ratings', 'comments')
+
+ACTIVITY_SHARING_SCHEMA = {key: Field(bool, required=False) for key in ACTIVITY_SHARING_KEYS}
+
+USER_ROLES = ('user', 'moderator', 'admin')
+
+ADMIN_SUSPEND_SCHEMA = {
+    'reason': Field(str, min_length=1, max_length=500),
+    'days': Field(int, required=False, min_value=1, max_value=365)
+}
+
+ADMIN_BAN_SCHEMA = {
+    'reason': Field(str, min_length=1, max_length=500)
+}
+
+ADMIN_ROLE_SCHEMA = {
+    'role': Field(str, choices=USER_ROLES)
+}
+
+
+def _register_checks(data, failed):
+    if 'password' in failed:
+        return []
+    username = data['username'] if 'username' not in failed else None
+    return [('password', m) for m in _password_strength_errors(data['password'], username)]
+
+
+def _reset_password_checks(data, failed):
+    if 'password' in failed:
+        return []
+    return [('password', m) for m in _password_strength_errors(data['password'])]
+
+
+def _comment_anchor_checks(data, failed):
+    """An episode anchor needs both numbers and only makes sense on TV"""
+    has_season, has_episode = data.get('season') is not None, data.get('episode') is not None
+    if not has_season and not has_episode:
+        return []
+    if data.get('content_type') != 'tv':
+        return [('season', 'episode anchors are only allowed on TV shows')]
+    if has_season != has_episode:
+        return [('episode' if has_season else 'season', 'season and episode must be given together')]
+    return []
+
+
+def _watch_progress_checks(data, failed):
+    """Progress on a show is kept per episode"""
+    if data.get('content_type') == 'tv' and (data.get('season') is None or data.get('episode') is None):
+        return [('episode', 'season and episode are required for TV shows')]
+    return []
+
+
+def _second_factor_checks(data, failed):
+    if not data.get('code') and not data.get('recovery_code'):
+        return [('code', 'a code or recovery_code is required')]
+    return []
+
+
+# ============= RATE LIMITING =============
+
+def _client_ip():
+    """
+    Client IP for sessions and rate limiting. X-Forwarded-For is only applied (by ProxyFix)
+    when TRUSTED_PROXY_COUNT is set, so clients can't pick their own address.
+    """
+    return request.remote_addr
+
+
+class MemoryRateLimitBackend:
+    """Process-local counters - fine for the single eventlet worker"""
+    def __init__(self):
+        self.counters = {}  # {key: (count, reset_at)}
+        self.state = {}  # {key: (value, expires_at)}
+        self.next_prune = 0
+        self.lock = threading.Lock()
+
+    def _prune_state(self, now):
+        """Drop expired state now and then - keys nobody reads again would otherwise stay forever"""
+        if now >= self.next_prune:
+            self.state = {k: v for k, v in self.state.items() if v[1] > now}
+            self.next_prune = now + 60
+
+    def incr(self, key, window):
+        """Count a hit in the current fixed window; returns (count, reset_at timestamp)"""
+        now = time.time()
+        with self.lock:
+            count, reset_at = self.counters.get(key, (0, 0))
+            if reset_at <= now:
+                count, reset_at = 0, now + window
+                # Drop other expired windows so the dict can't grow without bound
+                self.counters = {k: v for k, v in self.counters.items() if v[1] > now}
+            count += 1
+            self.counters[key] = (count, reset_at)
+            return count, reset_at
+
+    def get(self, key):
+        with self.lock:
+            value, expires_at = self.state.get(key, (None, 0))
+            if expires_at <= time.time():
+                self.state.pop(key, None)
+                return None
+            return value
+
+    def set(self, key, value, ttl):
+        with self.lock:
+            now = time.time()
+            self._prune_state(now)
+            self.state[key] = (value, now + ttl)
+
+    def incr_state(self, key, ttl):
+        """Add one to a stored count (0 if missing or expired) and keep it for ttl; returns the new count"""
+        with self.lock:
+            now = time.time()
+            self._prune_state(now)
+            value, expires_at = self.state.get(key, (0, 0))
+            value = (value if expires_at > now else 0) + 1
+            self.state[key] = (value, now + ttl)
+            return value
+
+    def delete(self, key):
+        with self.lock:
+            self.state.pop(key, None)
+
+
+class MongoRateLimitBackend:
+    """Counters in the rate_limits collection, shared across workers; a TTL index cleans up"""
+    def __init__(self, collection):
+        self.collection = collection
+
+    def incr(self, key, window):
+        now = time.time()
+        bucket = int(now // window)
+        reset_at = (bucket + 1) * window
+        doc = self.collection.find_one_and_update(
+            {'_id': f"count:{key}:{bucket}"},
+            {'$inc': {'count': 1}, '$setOnInsert': {'expires_at': datetime.utcfromtimestamp(reset_at)}},
+            upsert=True,
+            return_document=ReturnDocument.AFTER
+        )
+        return doc['count'], reset_at
+
+    def get(self, key):
+        doc = self.collection.find_one({'_id': f"state:{key}"})
+        # TTL cleanup runs about once a minute, so check expiry ourselves
+        if not doc or doc['expires_at'] <= datetime.utcnow():
+            return None
+        return doc['value']
+
+    def set(self, key, value, ttl):
+        self.collection.update_one(
+            {'_id': f"state:{key}"},
+            {'$set': {'value': value, 'expires_at': datetime.utcnow() + timedelta(seconds=ttl)}},
+            upsert=True
+        )
+
+    def incr_state(self, key, ttl):
+        now = datetime.utcnow()
+        # One update, so concurrent failures can't read the same count; an expired count starts over
+        doc = self.collection.find_one_and_update(
+            {'_id': f"state:{key}"},
+            [{'$set': {
+                'value': {'$cond': [
+                    {'$gt': [{'$ifNull': ['$expires_at', now]}, now]},
+                    {'$add': [{'$ifNull': ['$value', 0]}, 1]},
+                    1
+                ]},
+                'expires_at': now + timedelta(seconds=ttl)
+            }}],
+            upsert=True,
+            return_document=ReturnDocument.AFTER
+        )
+        return doc['value']
+
+    def delete(self, key):
+        self.collection.delete_one({'_id': f"state:{key}"})
+
+
+class RateLimiter:
+    def __init__(self, backend):
+        self.backend = backend
+
+    @staticmethod
+    def parse_limit(spec):
+        """'20/300' -> (20, 300)"""
+        count, seconds = spec.split('/')
+        return int(count), int(seconds)
+
+    def hit(self, name, identifier):
+        """Count a request against a configured limit; returns seconds to wait if it is exceeded"""
+        limit, window = self.parse_limit(app.config['RATE_LIMITS'][name])
+        count, reset_at = self.backend.incr(f"{name}:{identifier}", window)
+        if count > limit:
+            return max(1, int(reset_at - time.time()))
+        return None
+
+    def lockout_remaining(self, identifier):
+        """Seconds left on a login lockout for this identifier, or None"""
+        locked_until = self.backend.get(f"lock:{identifier}")
+        if locked_until and locked_until > time.time():
+            return max(1, int(locked_until - time.time()))
+        return None
+
+    def record_login_failure(self, identifier):
+        """
+        Count a failed login. Past the threshold each further failure locks the identifier
+        for twice as long as the previous one (capped). Returns the lockout length or None.
+        """
+        threshold = app.config['LOGIN_LOCKOUT_THRESHOLD']
+        max_seconds = app.config['LOGIN_LOCKOUT_MAX_SECONDS']
+
+        # Remember failures for a day so slow guessing still escalates
+        failures = self.backend.incr_state(f"failures:{identifier}", 86400)
+
+        if failures < threshold:
+            return None
+
+        lock_seconds = min(max_seconds, app.config['LOGIN_LOCKOUT_BASE_SECONDS'] * 2 ** (failures - threshold))
+        self.backend.set(f"lock:{identifier}", time.time() + lock_seconds, lock_seconds)
+        return lock_seconds
+
+    def reset_login_failures(self, identifier):
+        self.backend.delete(f"failures:{identifier}")
+        self.backend.delete(f"lock:{identifier}")
+
+
+def _create_rate_limiter():
+    """Pick the counter store from RATE_LIMIT_BACKEND (memory or mongo)"""
+    if app.config['RATE_LIMIT_BACKEND'].lower() == 'mongo':
+        return RateLimiter(MongoRateLimitBackend(rate_limits_collection))
+    return RateLimiter(MemoryRateLimitBackend())
+
+rate_limiter = _create_rate_limiter()
+
+
+def _too_many_requests(retry_after, message='Too many requests, please try again later'):
+    response = jsonify({'error': message, 'retry_after': retry_after})
+    response.status_code = 429
+    response.headers['Retry-After'] = str(retry_after)
+    return response
+
+
+def rate_limit(name, per_account=None):
+    """
+    Apply the '<name>_ip' limit (and '<name>_account' when per_account is given).
+    per_account() returns the account identifier for the current request, or None.
+    """
+    def decorator(fn):
+        @functools.wraps(fn)
+        def wrapper(*args, **kwargs):
+            retry_after = rate_limiter.hit(f"{name}_ip", _client_ip())
+            if retry_after is None and per_account:
+                account = per_account()
+                if account:
+                    retry_after = rate_limiter.hit(f"{name}_account", account)
+            if retry_after is not None:
+                return _too_many_requests(retry_after)
+            return fn(*args, **kwargs)
+        return wrapper
+    return decorator
+
+
+def _login_account():
+    data = request.get_json(silent=True) or {}
+    username = data.get('username')
+    return username.lower() if isinstance(username, str) else None
+
+
+# ============= ROLES & ACCOUNT STATUS =============
+
+ROLE_LEVELS = {'user': 0, 'moderator': 1, 'admin': 2}
+
+
+def _user_role(user):
+    return user.get('role', 'user') if user else 'user'
+
+
+def _account_block_reason(user):
+    """Why a user may not sign in right now (banned / suspended), or None"""
+    status = user.get('status', 'active')
+    if status == 'banned':
+        return 'This account has been banned'
+    if status == 'suspended':
+        until = user.get('suspended_until')
+        if until is None or until > datetime.utcnow():
+            return f"This account is suspended until {until.isoformat()}" if until else 'This account is suspended'
+    return None
+
+
+def role_required(minimum_role):
+    """
+    Restrict a view to users with at least the given role (user < moderator < admin).
+    Use below @jwt_required(); the acting user document is left in g.current_user.
+    """
+    def decorator(fn):
+        @functools.wraps(fn)
+        def wrapper(*args, **kwargs):
+            user_id = get_jwt_identity()
+            try:
+                user = users_collection.find_one({'_id': ObjectId(user_id)})
+            except Exception:
+                user = None
+            if not user or _account_block_reason(user):
+                return jsonify({'error': 'Forbidden'}), 403
+            if ROLE_LEVELS.get(_user_role(user), 0) < ROLE_LEVELS[minimum_role]:
+                return jsonify({'error': f'{minimum_role.capitalize()} role required'}), 403
+            g.current_user = user
+            return fn(*args, **kwargs)
+        return wrapper
+    return decorator
+
+
+def _audit(action, target_user_id=None, details=None):
+    """Record an admin/moderator action; the actor comes from g.current_user"""
+    actor = getattr(g, 'current_user', None) or {}
+    admin_audit_log_collection.insert_one({
+        'actor_id': actor.get('_id'),
+        'actor_username': actor.get('username'),
+        'action': action,
+        'target_user_id': ObjectId(target_user_id) if target_user_id else None,
+        'details': details or {},
+        'ip': _client_ip(),
+        'created_at': datetime.utcnow()
+    })
+
+
 # ==== Ratings & Friends (MongoDB) =============================================
 
 def _content_key(content_type, tmdb_id):
     return f"{'tv' if content_type == 'tv' else 'movie'}:{int(tmdb_id)}"
 
+def _episode_key(tv_id, season_number, episode_number):
+    """Episode ratings share the ratings collection under tv:<id>:s<season>e<episode>"""
+    return f"tv:{int(tv_id)}:s{int(season_number)}e{int(episode_number)}"
+
+def _episode_fields(tv_id, season_number, episode_number):
+    """Stored alongside episode ratings so seasons and shows can be aggregated"""
+    return {"tv_id": int(tv_id), "season": int(season_number), "episode": int(episode_number)}
+
 def _identity_to_user(identity):
     """
     Resolve a user doc from JWT identity. Handles:
@@ -1125,51 +2040,113 @@ def _identity_to_user(identity):
         pass
     return None
 
+# ---------- Friendships ----------
+# An accepted friend request is the one and only record of a friendship; whoever
+# sent it, it counts for both sides. Everything that needs a friends list goes through here.
+
+def _friendship_filter(user_id, other_id=None):
+    """Query for accepted friend_requests involving user_id (and other_id, if given)"""
+    user_id = ObjectId(user_id)
+    if other_id is None:
+        return {'status': 'accepted', '$or': [{'from_user_id': user_id}, {'to_user_id': user_id}]}
+    other_id = ObjectId(other_id)
+    return {'status': 'accepted', '$or': [
+        {'from_user_id': user_id, 'to_user_id': other_id},
+        {'from_user_id': other_id, 'to_user_id': user_id}
+    ]}
+
+
+def _friend_ids(user_id):
+    """ObjectIds of a user's friends"""
+    user_id = ObjectId(user_id)
+    accepted = friend_requests_collection.find(_friendship_filter(user_id), {'from_user_id': 1, 'to_user_id': 1})
+    return list({fr['to_user_id'] if fr['from_user_id'] == user_id else fr['from_user_id'] for fr in accepted})
+
+
+def _are_friends(user_id, other_id):
+    return friend_requests_collection.count_documents(_friendship_filter(user_id, other_id), limit=1) > 0
+
+
 def _get_friends_usernames_for(user_id: ObjectId):
+    """Usernames of a user's friends"""
+    return [u['username'] for u in users_collection.find({'_id': {'$in': _friend_ids(user_id)}}, {'username': 1})]
+
+# ---------- Ratings ----------
+def _rating_histogram(key):
+    """Global average, count and 1-10 histogram for a title (ratings rounded half up to whole stars)"""
+    buckets = {doc['_id']: doc for doc in ratings_collection.aggregate([
+        {'$match': {'content_key': key}},
+        {'$group': {
+            '_id': {'$min': [10, {'$max': [1, {'$floor': {'$add': ['$rating', 0.5]}}]}]},
+            'count': {'$sum': 1},
+            'total': {'$sum': '$rating'}
+        }}
+    ])}
+    count = sum(b['count'] for b in buckets.values())
+    total = sum(b['total'] for b in buckets.values())
+    histogram = [{'score': score, 'count': buckets.get(score, {}).get('count', 0)} for score in range(1, 11)]
+    return (round(total / count, 2) if count else None), count, histogram
+
+
+def _ratings_response(key):
     """
-    Build friend list from friend_requests_collection with status 'accepted'.
-    Returns a list of usernames (strings).
+    Average and count for everyone. Signed-in viewers also get ratings_by_user (anonymous
+    callers don't). ?view=breakdown adds friends-only and global averages, a 1-10 histogram
+    and the viewer's own rating.
     """
-    friends = set()
-    try:
-        accepted = friend_requests_collection.find(
-            {
-                "status": "accepted",
-                "$or": [{"requester_id": user_id}, {"receiver_id": user_id}]
-            },
-            {"requester_id": 1, "receiver_id": 1}
-        )
-        for fr in accepted:
-            other_id = fr["receiver_id"] if fr.get("requester_id") == user_id else fr.get("requester_id")
-            if other_id:
-                u = users_collection.find_one({"_id": other_id}, {"username": 1})
-                if u and u.get("username"):
-                    friends.add(u["username"])
-    except Exception as e:
-        print(f"friends lookup error: {e}")
-    return list(friends)
+    average, count, histogram = _rating_histogram(key)
+    result = {"average": average, "count": count}
+
+    identity = get_jwt_identity()
+    viewer = _identity_to_user(identity) if identity else None
+    if viewer:
+        friend_ids = set(_friend_ids(viewer["_id"]))
+        cursor = ratings_collection.find({"content_key": key}, {"user_id": 1, "username": 1, "rating": 1})
+        mine, friends, ratings_by_user = None, {}, {}
+        for doc in cursor:
+            if "username" not in doc or "rating" not in doc:
+                continue
+            ratings_by_user[doc["username"]] = float(doc["rating"])
+            if doc["user_id"] == viewer["_id"]:
+                mine = float(doc["rating"])
+            elif doc["user_id"] in friend_ids:
+                friends[doc["username"]] = float(doc["rating"])
+        result["ratings_by_user"] = ratings_by_user
+
+    if request.args.get('view') == 'breakdown':
+        result["global"] = {"average": average, "count": count}
+        result["histogram"] = histogram
+        if viewer:
+            values = list(friends.values())
+            result["friends"] = {
+                "average": round(sum(values) / len(values), 2) if values else None,
+                "count": len(values)
+            }
+            result["my_rating"] = mine
+
+    return jsonify(result)
+
 
-# ---------- Ratings ----------
 @app.route('/api/ratings/<content_type>/<int:tmdb_id>', methods=['GET'])
+@jwt_required(optional=True)
 def get_ratings(content_type, tmdb_id):
-    key = _content_key(content_type, tmdb_id)
-    print(f"[Ratings] GET {content_type}/{tmdb_id}, key: {key}")
-    cursor = ratings_collection.find({"content_key": key}, {"username": 1, "rating": 1})
-    ratings_by_user = {doc["username"]: float(doc["rating"]) for doc in cursor if "username" in doc and "rating" in doc}
-    print(f"[Ratings] Found {len(ratings_by_user)} ratings: {ratings_by_user}")
-    values = list(ratings_by_user.values())
-    avg = round(sum(values) / len(values), 2) if values else None
-    return jsonify({
-        "ratings_by_user": ratings_by_user,
-        "average": avg,
-        "count": len(values)
-    })
+    return _ratings_response(_content_key(content_type, tmdb_id))
+
+
+@app.route('/api/ratings/tv/<int:tv_id>/season/<int:season_number>/episode/<int:episode_number>', methods=['GET'])
+@jwt_required(optional=True)
+def get_episode_ratings(tv_id, season_number, episode_number):
+    return _ratings_response(_episode_key(tv_id, season_number, episode_number))
 
 from flask_jwt_extended import jwt_required, get_jwt_identity
 
-@app.route('/api/ratings/<content_type>/<int:tmdb_id>', methods=['POST'])
-@jwt_required()
-def post_rating(content_type, tmdb_id):
+def _rated_content(data):
+    """The title and poster sent with a rating or review, for storing on the rating"""
+    return {field: data[field] for field in RATED_CONTENT_FIELDS if data.get(field)}
+
+
+def _save_rating(key, extra=None):
+    """Upsert the caller's rating for a title or episode and return the fresh aggregate"""
     identity = get_jwt_identity()
     udoc = _identity_to_user(identity)
     if not udoc:
@@ -1183,130 +2160,487 @@ def post_rating(content_type, tmdb_id):
     if not (1.0 <= rating <= 10.0):
         return jsonify({"error": "Rating must be between 1 and 10"}), 400
 
-    key = _content_key(content_type, tmdb_id)
     now = datetime.utcnow().isoformat()
 
-    print(f"[Ratings] POST {content_type}/{tmdb_id} by {udoc['username']}: rating={rating}")
-    print(f"[Ratings] Updating with key={key}, user_id={udoc['_id']}")
-
-    result = ratings_collection.update_one(
+    ratings_collection.update_one(
         {"content_key": key, "user_id": udoc["_id"]},
-        {"$set": {"username": udoc["username"], "rating": round(rating, 1), "updated_at": now}},
+        {"$set": {"username": udoc["username"], "rating": round(rating, 1), "updated_at": now,
+                  **_rated_content(data), **(extra or {})}},
         upsert=True
     )
 
-    print(f"[Ratings] Update result: matched={result.matched_count}, modified={result.modified_count}, upserted_id={result.upserted_id}")
-
     # Return fresh aggregate
-    return get_ratings(content_type, tmdb_id)
+    return _ratings_response(key)
 
-# ---------- Friends ----------
-## Duplicate route removed - using the one at line 3037 instead
-# @app.route('/api/friends', methods=['GET'])
-# @jwt_required()
-# def get_friends_me():
-#     identity = get_jwt_identity()
-#     udoc = _identity_to_user(identity)
-#     if not udoc:
-#         return jsonify({"error": "User not found"}), 401
-#     friends = _get_friends_usernames_for(udoc["_id"])
-#     return jsonify({"friends": [{"username": u} for u in friends]})
 
+@app.route('/api/ratings/<content_type>/<int:tmdb_id>', methods=['POST'])
+@jwt_required()
+@validate_json(RATING_SCHEMA)
+def post_rating(content_type, tmdb_id):
+    return _save_rating(_content_key(content_type, tmdb_id))
 
 
-@app.route('/api/movie/<int:movie_id>')
-def get_movie_details(movie_id):
-    """Get detailed movie information with OMDB enhancement and cast"""
-    movie = tmdb.get_movie_details(movie_id)
+@app.route('/api/ratings/tv/<int:tv_id>/season/<int:season_number>/episode/<int:episode_number>', methods=['POST'])
+@jwt_required()
+@validate_json(RATING_SCHEMA)
+def post_episode_rating(tv_id, season_number, episode_number):
+    return _save_rating(_episode_key(tv_id, season_number, episode_number),
+                        _episode_fields(tv_id, season_number, episode_number))
 
-    # Fetch OMDB data if we have an IMDb ID
-    imdb_id = movie.get('external_ids', {}).get('imdb_id')
-    print(f"Movie ID: {movie_id}, IMDb ID: {imdb_id}")
 
-    if imdb_id:
-        omdb_data = get_omdb_data(imdb_id)
-        print(f"OMDB data received: {omdb_data}")
-        movie.update(omdb_data)
+def _stats(total, count):
+    return {'average': round(total / count, 2) if count else None, 'count': count}
 
-    # Fetch cast and crew data from TMDB
-    credits = tmdb.get_movie_credits(movie_id)
-    if 'cast' in credits:
-        movie['tmdb_cast'] = credits['cast'][:5]  # Top 5 cast members
-    if 'crew' in credits:
-        movie['tmdb_crew'] = credits['crew']
 
-    return jsonify(movie)
+def _episode_rating_groups(match, group_by, viewer_id, friend_ids):
+    """
+    Community, friends and the viewer's own average over episode ratings matching match,
+    grouped by '$episode' or '$season'. {group value: {community, friends, mine}}
+    """
+    is_friend = {'$in': ['$user_id', friend_ids]}
+    groups = ratings_collection.aggregate([
+        {'$match': {**match, 'episode': {'$exists': True}}},
+        {'$group': {
+            '_id': group_by,
+            'total': {'$sum': '$rating'},
+            'count': {'$sum': 1},
+            'friend_total': {'$sum': {'$cond': [is_friend, '$rating', 0]}},
+            'friend_count': {'$sum': {'$cond': [is_friend, 1, 0]}},
+            'mine': {'$avg': {'$cond': [{'$eq': ['$user_id', viewer_id]}, '$rating', None]}}
+        }}
+    ])
+    return {g['_id']: {
+        'community': _stats(g['total'], g['count']),
+        'friends': _stats(g['friend_total'], g['friend_count']),
+        'mine': round(g['mine'], 2) if g['mine'] is not None else None
+    } for g in groups}
+
+
+def _viewer_and_friends():
+    identity = get_jwt_identity()
+    viewer = _identity_to_user(identity) if identity else None
+    return (viewer['_id'], _friend_ids(viewer['_id'])) if viewer else (None, [])
 
 
+@app.route('/api/ratings/tv/<int:tv_id>/season/<int:season_number>', methods=['GET'])
+@jwt_required(optional=True)
+def get_season_ratings(tv_id, season_number):
+    """
+    Every episode in a season with IMDb, community and friends scores side by side, plus
+    season totals. ?imdb=false skips the OMDB lookups (which are slow the first time).
+    """
+    try:
+        viewer_id, friend_ids = _viewer_and_friends()
+        by_episode = _episode_rating_groups({'tv_id': tv_id, 'season': season_number}, '$episode', viewer_id, friend_ids)
+        season_total = _episode_rating_groups({'tv_id': tv_id, 'season': season_number}, '$season', viewer_id, friend_ids)
+
+        include_imdb = request.args.get('imdb', 'true').lower() != 'false'
+        season = tmdb.get_tv_season_details(tv_id, season_number, include_imdb)
+        empty = {'community': _stats(0, 0), 'friends': _stats(0, 0), 'mine': None}
+
+        episodes = []
+        listed = set()
+        for episode in season.get('episodes', []) if not season.get('error') else []:
+            number = episode['episode_number']
+            listed.add(number)
+            imdb = episode.get('omdb_imdb_rating')
+            episodes.append({
+                'episode_number': number,
+                'name': episode.get('name'),
+                'air_date': episode.get('air_date'),
+                'imdb_rating': imdb if imdb and imdb != 'N/A' else None,
+                **by_episode.get(number, empty)
+            })
+        # Rated episodes TMDB didn't list (or when TMDB is unavailable)
+        for number in sorted(set(by_episode) - listed):
+            episodes.append({'episode_number': number, 'name': None, 'air_date': None, 'imdb_rating': None, **by_episode[number]})
 
-@app.route('/api/popular')
-def get_popular_movies():
-    """Get popular movies"""
-    page = request.args.get('page', 1, type=int)
-    language = request.args.get('language')
-    results = tmdb.get_popular_movies(page, language)
-    return jsonify(results)
+        imdb_scores = [float(e['imdb_rating']) for e in episodes if e['imdb_rating']]
+        return jsonify({
+            'tv_id': tv_id,
+            'season_number': season_number,
+            'season': {
+                **season_total.get(season_number, empty),
+                'imdb_average': round(sum(imdb_scores) / len(imdb_scores), 2) if imdb_scores else None
+            },
+            'episodes': episodes
+        }), 200
 
-@app.route('/api/trending')
-def get_trending_movies():
-    """Get trending movies"""
-    time_window = request.args.get('time_window', 'day')
-    if time_window not in ['day', 'week']:
-        time_window = 'day'
-    language = request.args.get('language')
-    page = request.args.get('page', 1, type=int)
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-    results = tmdb.get_trending_movies(time_window, language, page)
-    return jsonify(results)
 
-# TV Show Endpoints
+@app.route('/api/ratings/tv/<int:tv_id>/seasons', methods=['GET'])
+@jwt_required(optional=True)
+def get_show_episode_ratings(tv_id):
+    """Episode scores rolled up per season and for the whole show, next to the show's own rating"""
+    try:
+        viewer_id, friend_ids = _viewer_and_friends()
+        by_season = _episode_rating_groups({'tv_id': tv_id}, '$season', viewer_id, friend_ids)
+        overall = _episode_rating_groups({'tv_id': tv_id}, None, viewer_id, friend_ids).get(None)
+        show_average, show_count, _ = _rating_histogram(_content_key('tv', tv_id))
 
-@app.route('/api/tv/search')
-def search_tv_shows():
-    """Search for TV shows"""
-    query = request.args.get('query', '').strip()
-    year = request.args.get('year')
-    page = request.args.get('page', 1, type=int)
+        return jsonify({
+            'tv_id': tv_id,
+            'show_rating': {'average': show_average, 'count': show_count},
+            'episodes_overall': overall or {'community': _stats(0, 0), 'friends': _stats(0, 0), 'mine': None},
+            'seasons': [{'season_number': number, **by_season[number]} for number in sorted(by_season)]
+        }), 200
 
-    if not query:
-        return jsonify({"error": "Query parameter is required"}), 400
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-    try:
-        year = int(year) if year else None
-    except ValueError:
-        year = None
+# ---------- Reviews ----------
+# A review is stored on the reviewer's ratings document (review: {title, body, spoiler,
+# helpful_count, created_at, updated_at}); its id is the rating's id. Helpful votes live in
+# review_helpful so each user can vote once.
+
+def _content_from_key(content_key):
+    """('movie' | 'tv', tmdb id) - episode keys resolve to their show"""
+    content_type, content_id = content_key.split(':')[:2]
+    return content_type, int(content_id)
+
+
+def _review_response(doc, viewer_id, friend_ids=(), voted=(), authors=None):
+    review = doc['review']
+    author = (authors or {}).get(doc['user_id']) or {}
+    profile = author.get('profile') or {}
+    content_type, content_id = _content_from_key(doc['content_key'])
+    return {
+        'id': str(doc['_id']),
+        'content_type': content_type,
+        'content_id': content_id,
+        'season': doc.get('season'),
+        'episode': doc.get('episode'),
+        'user': {
+            'id': str(doc['user_id']),
+            'username': doc.get('username'),
+            'display_name': profile.get('display_name') or doc.get('username'),
+            'avatar_url': _avatar_url(profile)
+        },
+        'rating': doc.get('rating'),
+        'content': {'title': doc.get('content_title'), 'poster_path': doc.get('poster_path')},
+        'title': review['title'],
+        'body': review['body'],
+        'spoiler': review.get('spoiler', False),
+        # Other people's spoilers start collapsed, like spoiler comments
+        'collapsed': bool(review.get('spoiler')) and doc['user_id'] != viewer_id,
+        'helpful_count': review.get('helpful_count', 0),
+        'marked_helpful': doc['_id'] in voted,
+        'is_friend': doc['user_id'] in friend_ids,
+        'created_at': review['created_at'].isoformat(),
+        'updated_at': review['updated_at'].isoformat() if review.get('updated_at') else None
+    }
 
-    results = tmdb.search_tv_shows(query, year, page)
-    return jsonify(results)
 
-@app.route('/api/search/multi')
-def search_multi():
-    """Search for both movies and TV shows"""
-    query = request.args.get('query', '').strip()
-    page = request.args.get('page', 1, type=int)
+def _review_page(query, viewer_id, offset, limit, friends_first=False):
+    """
+    One page of reviews matching query, with author details and the viewer's helpful votes.
+    Reviews by authors whose profile the viewer can't see (see _profile_access) are left out.
+    """
+    friend_ids = _friend_ids(viewer_id) if viewer_id else []
+    pipeline = [
+        {'$match': {**query, 'review': {'$exists': True}}},
+        {'$lookup': {'from': users_collection.name, 'localField': 'user_id', 'foreignField': '_id', 'as': 'author'}},
+        {'$match': {'$or': [
+            {'user_id': viewer_id},
+            {'author.profile.visibility': {'$nin': ['private', 'friends']}},
+            {'author.profile.visibility': 'friends', 'user_id': {'$in': friend_ids}}
+        ]}},
+        {'$project': {'author': 0}}
+    ]
+    if friends_first:
+        pipeline.append({'$addFields': {
+            'is_mine': {'$eq': ['$user_id', viewer_id]},
+            'is_friend': {'$in': ['$user_id', friend_ids]}
+        }})
+        sort = {'is_mine': -1, 'is_friend': -1, 'review.helpful_count': -1, 'review.created_at': -1, '_id': -1}
+    else:
+        sort = {'review.created_at': -1, '_id': -1}
+    pipeline += [{'$sort': sort}, {'$skip': offset}, {'$limit': limit + 1}]
 
-    if not query:
-        return jsonify({"error": "Query parameter is required"}), 400
+    docs = list(ratings_collection.aggregate(pipeline))
+    next_offset = offset + limit if len(docs) > limit else None
+    docs = docs[:limit]
 
-    results = tmdb.search_multi(query, page)
-    return jsonify(results)
+    authors = {u['_id']: u for u in users_collection.find({'_id': {'$in': [d['user_id'] for d in docs]}}, {'profile': 1})}
+    voted = set()
+    if viewer_id and docs:
+        voted = {v['review_id'] for v in review_helpful_collection.find(
+            {'user_id': viewer_id, 'review_id': {'$in': [d['_id'] for d in docs]}}, {'review_id': 1})}
+    return [_review_response(d, viewer_id, friend_ids, voted, authors) for d in docs], next_offset
 
-@app.route('/api/tv/<int:tv_id>')
-def get_tv_details(tv_id):
-    """Get detailed TV show information with OMDB enhancement and cast"""
-    show = tmdb.get_tv_details(tv_id)
 
-    # Fetch OMDB data if we have an IMDb ID
-    imdb_id = show.get('external_ids', {}).get('imdb_id')
-    print(f"TV ID: {tv_id}, IMDb ID: {imdb_id}")
+def _page_args(default_limit=20, max_limit=50):
+    limit = min(max_limit, max(1, request.args.get('limit', default_limit, type=int)))
+    offset = max(0, request.args.get('offset', 0, type=int))
+    return offset, limit
 
-    if imdb_id:
-        omdb_data = get_omdb_data(imdb_id)
-        print(f"OMDB data received: {omdb_data}")
-        show.update(omdb_data)
 
-    # Fetch cast and crew data from TMDB
-    credits = tmdb.get_tv_credits(tv_id)
+def _save_review(key, extra=None):
+    """Write or edit the caller's review of a title or episode. They need a rating first, or to send one with the review."""
+    try:
+        udoc = _identity_to_user(get_jwt_identity())
+        if not udoc:
+            return jsonify({'error': 'User not found'}), 401
+        data = request.get_json()
+        now = datetime.utcnow()
+
+        existing = ratings_collection.find_one({'content_key': key, 'user_id': udoc['_id']})
+        if not existing and data.get('rating') is None:
+            return jsonify({'error': f"Rate this {'episode' if extra else 'title'} before reviewing it"}), 400
+
+        updates = {
+            **(extra or {}),
+            **_rated_content(data),
+            'username': udoc['username'],
+            'review.title': data['title'].strip(),
+            'review.body': data['body'].strip(),
+            'review.spoiler': bool(data.get('spoiler')),
+            'review.updated_at': now
+        }
+        if data.get('rating') is not None:
+            updates.update({'rating': round(float(data['rating']), 1), 'updated_at': now.isoformat()})
+        if not (existing or {}).get('review'):
+            updates.update({'review.created_at': now, 'review.helpful_count': 0})
+
+        doc = ratings_collection.find_one_and_update(
+            {'content_key': key, 'user_id': udoc['_id']},
+            {'$set': updates},
+            upsert=True,
+            return_document=ReturnDocument.AFTER
+        )
+        return jsonify(_review_response(doc, udoc['_id'])), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+def _delete_review(key):
+    """Remove the caller's review; the rating itself stays"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        doc = ratings_collection.find_one_and_update(
+            {'content_key': key, 'user_id': user_id, 'review': {'$exists': True}},
+            {'$unset': {'review': ''}}
+        )
+        if not doc:
+            return jsonify({'error': 'Review not found'}), 404
+        review_helpful_collection.delete_many({'review_id': doc['_id']})
+        return jsonify({'message': 'Review deleted'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+def _list_reviews(key):
+    """
+    Reviews of a title or episode: yours, then friends', then everyone else's by helpful votes.
+    ?limit= (max 50), ?offset= the previous page's next_offset.
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        user = users_collection.find_one({'_id': user_id}, {'blocked_users': 1, 'muted_users': 1})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        offset, limit = _page_args()
+
+        query = {'content_key': key, 'user_id': {'$nin': _hidden_author_ids(user)}}
+        reviews, next_offset = _review_page(query, user_id, offset, limit, friends_first=True)
+        return jsonify({'reviews': reviews, 'next_offset': next_offset}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/ratings/<content_type>/<int:tmdb_id>/review', methods=['PUT'])
+@jwt_required()
+@validate_json(REVIEW_SCHEMA)
+def put_review(content_type, tmdb_id):
+    return _save_review(_content_key(content_type, tmdb_id))
+
+
+@app.route('/api/ratings/<content_type>/<int:tmdb_id>/review', methods=['DELETE'])
+@jwt_required()
+def delete_review(content_type, tmdb_id):
+    return _delete_review(_content_key(content_type, tmdb_id))
+
+
+@app.route('/api/ratings/<content_type>/<int:tmdb_id>/reviews', methods=['GET'])
+@jwt_required()
+def get_reviews(content_type, tmdb_id):
+    return _list_reviews(_content_key(content_type, tmdb_id))
+
+
+@app.route('/api/ratings/tv/<int:tv_id>/season/<int:season_number>/episode/<int:episode_number>/review', methods=['PUT'])
+@jwt_required()
+@validate_json(REVIEW_SCHEMA)
+def put_episode_review(tv_id, season_number, episode_number):
+    return _save_review(_episode_key(tv_id, season_number, episode_number),
+                        _episode_fields(tv_id, season_number, episode_number))
+
+
+@app.route('/api/ratings/tv/<int:tv_id>/season/<int:season_number>/episode/<int:episode_number>/review', methods=['DELETE'])
+@jwt_required()
+def delete_episode_review(tv_id, season_number, episode_number):
+    return _delete_review(_episode_key(tv_id, season_number, episode_number))
+
+
+@app.route('/api/ratings/tv/<int:tv_id>/season/<int:season_number>/episode/<int:episode_number>/reviews', methods=['GET'])
+@jwt_required()
+def get_episode_reviews(tv_id, season_number, episode_number):
+    return _list_reviews(_episode_key(tv_id, season_number, episode_number))
+
+
+@app.route('/api/reviews/<review_id>/helpful', methods=['POST'])
+@jwt_required()
+def mark_review_helpful(review_id):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        doc = ratings_collection.find_one({'_id': ObjectId(review_id), 'review': {'$exists': True}}, {'user_id': 1})
+        if not doc or _is_blocked_between(user_id, doc['user_id']):
+            return jsonify({'error': 'Review not found'}), 404
+        if doc['user_id'] == user_id:
+            return jsonify({'error': "You can't mark your own review helpful"}), 400
+
+        try:
+            review_helpful_collection.insert_one({'review_id': doc['_id'], 'user_id': user_id, 'created_at': datetime.utcnow()})
+            doc = ratings_collection.find_one_and_update(
+                {'_id': doc['_id']}, {'$inc': {'review.helpful_count': 1}}, return_document=ReturnDocument.AFTER)
+        except DuplicateKeyError:
+            doc = ratings_collection.find_one({'_id': doc['_id']})
+
+        return jsonify({'helpful_count': doc['review'].get('helpful_count', 0), 'marked_helpful': True}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/reviews/<review_id>/helpful', methods=['DELETE'])
+@jwt_required()
+def unmark_review_helpful(review_id):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        removed = review_helpful_collection.delete_one({'review_id': ObjectId(review_id), 'user_id': user_id}).deleted_count
+        doc = ratings_collection.find_one_and_update(
+            {'_id': ObjectId(review_id), 'review': {'$exists': True}},
+            {'$inc': {'review.helpful_count': -removed}},
+            return_document=ReturnDocument.AFTER
+        )
+        if not doc:
+            return jsonify({'error': 'Review not found'}), 404
+        return jsonify({'helpful_count': doc['review'].get('helpful_count', 0), 'marked_helpful': False}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+# ---------- Friends ----------
+## Duplicate route removed - using the one at line 3037 instead
+# @app.route('/api/friends', methods=['GET'])
+# @jwt_required()
+# def get_friends_me():
+#     identity = get_jwt_identity()
+#     udoc = _identity_to_user(identity)
+#     if not udoc:
+#         return jsonify({"error": "User not found"}), 401
+#     friends = _get_friends_usernames_for(udoc["_id"])
+#     return jsonify({"friends": [{"username": u} for u in friends]})
+
+
+
+@app.route('/api/movie/<int:movie_id>')
+def get_movie_details(movie_id):
+    """Get detailed movie information with OMDB enhancement and cast"""
+    movie = tmdb.get_movie_details(movie_id)
+
+    # Fetch OMDB data if we have an IMDb ID
+    imdb_id = movie.get('external_ids', {}).get('imdb_id')
+    print(f"Movie ID: {movie_id}, IMDb ID: {imdb_id}")
+
+    if imdb_id:
+        omdb_data = get_omdb_data(imdb_id)
+        print(f"OMDB data received: {omdb_data}")
+        movie.update(omdb_data)
+
+    # Fetch cast and crew data from TMDB
+    credits = tmdb.get_movie_credits(movie_id)
+    if 'cast' in credits:
+        movie['tmdb_cast'] = credits['cast'][:5]  # Top 5 cast members
+    if 'crew' in credits:
+        movie['tmdb_crew'] = credits['crew']
+
+    return jsonify(movie)
+
+
+
+@app.route('/api/popular')
+def get_popular_movies():
+    """Get popular movies"""
+    page = request.args.get('page', 1, type=int)
+    language = request.args.get('language')
+    results = tmdb.get_popular_movies(page, language)
+    return jsonify(results)
+
+@app.route('/api/trending')
+def get_trending_movies():
+    """Get trending movies"""
+    time_window = request.args.get('time_window', 'day')
+    if time_window not in ['day', 'week']:
+        time_window = 'day'
+    language = request.args.get('language')
+    page = request.args.get('page', 1, type=int)
+
+    results = tmdb.get_trending_movies(time_window, language, page)
+    return jsonify(results)
+
+# TV Show Endpoints
+
+@app.route('/api/tv/search')
+def search_tv_shows():
+    """Search for TV shows"""
+    query = request.args.get('query', '').strip()
+    year = request.args.get('year')
+    page = request.args.get('page', 1, type=int)
+
+    if not query:
+        return jsonify({"error": "Query parameter is required"}), 400
+
+    try:
+        year = int(year) if year else None
+    except ValueError:
+        year = None
+
+    results = tmdb.search_tv_shows(query, year, page)
+    return jsonify(results)
+
+@app.route('/api/search/multi')
+def search_multi():
+    """Search for both movies and TV shows"""
+    query = request.args.get('query', '').strip()
+    page = request.args.get('page', 1, type=int)
+
+    if not query:
+        return jsonify({"error": "Query parameter is required"}), 400
+
+    results = tmdb.search_multi(query, page)
+    return jsonify(results)
+
+@app.route('/api/tv/<int:tv_id>')
+def get_tv_details(tv_id):
+    """Get detailed TV show information with OMDB enhancement and cast"""
+    show = tmdb.get_tv_details(tv_id)
+
+    # Fetch OMDB data if we have an IMDb ID
+    imdb_id = show.get('external_ids', {}).get('imdb_id')
+    print(f"TV ID: {tv_id}, IMDb ID: {imdb_id}")
+
+    if imdb_id:
+        omdb_data = get_omdb_data(imdb_id)
+        print(f"OMDB data received: {omdb_data}")
+        show.update(omdb_data)
+
+    # Fetch cast and crew data from TMDB
+    credits = tmdb.get_tv_credits(tv_id)
     if 'cast' in credits:
         show['tmdb_cast'] = credits['cast'][:5]  # Top 5 cast members
     if 'crew' in credits:
@@ -2247,6 +3581,186 @@ def proxy_hls():
         print(f"[HLS Proxy] Error: {e}")
         return jsonify({"error": str(e)}), 500
 
+# ============= REALTIME (SOCKET.IO) =============
+# Sockets that present an access token join a personal room (user:<id>) which REST handlers
+# push notifications and friend updates to. Viewers of a title's comments join content:<type>:<id>.
+# Presence is process-local, like the watchparty rooms.
+
+socket_users = {}  # {sid: user_id}
+socket_sessions = {}  # {sid: {'session_id': str or None, 'expires_at': datetime}} of the token each socket signed in with
+socket_content_rooms = {}  # {sid: content room}
+online_users = {}  # {user_id: {'username': str, 'sids': set(), 'watching': dict or None}}
+
+
+def _user_room(user_id):
+    return f'user:{user_id}'
+
+
+def _content_room(content_type, content_id):
+    return f'content:{content_type}:{content_id}'
+
+
+def _push_to_user(user_id, event, data):
+    # Sockets whose access token has run out leave the room first; auth_error asks them to sign in again
+    now = datetime.utcnow()
+    for sid in list((online_users.get(user_id) or {}).get('sids', ())):
+        expires_at = socket_sessions.get(sid, {}).get('expires_at')
+        if expires_at and expires_at <= now:
+            _socket_sign_out(sid)
+            socketio.emit('auth_error', {'error': 'Invalid or expired token'}, room=sid)
+    socketio.emit(event, data, room=_user_room(user_id))
+
+
+def _comments_changed(comment, action):
+    """
+    Tell everyone viewing a title's comments that something changed. Only ids are sent -
+    each client refetches so the usual visibility rules apply to what it gets back.
+    """
+    socketio.emit('comments_changed', {
+        'content_id': comment.get('content_id'),
+        'content_type': comment.get('content_type'),
+        'comment_id': str(comment['_id']),
+        'action': action
+    }, room=_content_room(comment.get('content_type'), comment.get('content_id')))
+
+
+def _presence_payload(user_id):
+    entry = online_users.get(user_id)
+    return {
+        'user_id': user_id,
+        'online': entry is not None,
+        # What they're watching is only shown if they share it with friends
+        'watching': entry['watching'] if entry and entry.get('shares_watching') else None
+    }
+
+
+def _broadcast_presence(user_id):
+    """Send a user's presence to their friends who are online"""
+    payload = _presence_payload(user_id)
+    for friend_id in _friend_ids(user_id):
+        if str(friend_id) in online_users:
+            _push_to_user(str(friend_id), 'presence', payload)
+
+
+def _socket_user_from_token(token):
+    """(user, token payload) for a valid, unrevoked access token whose user may sign in, or (None, None)"""
+    try:
+        payload = decode_token(token)
+    except Exception:
+        return None, None
+    if payload.get('type') != 'access' or check_if_token_revoked({}, payload):
+        return None, None
+    user = users_collection.find_one({'_id': ObjectId(payload['sub'])})
+    if not user or _account_block_reason(user):
+        return None, None
+    return user, payload
+
+
+def _socket_sign_in(user, payload):
+    sid = request.sid
+    user_id = str(user['_id'])
+    # Remember which session and token this is, so revoking the session or the token running out ends it
+    socket_sessions[sid] = {
+        'session_id': payload.get('sid'),
+        'expires_at': datetime.utcfromtimestamp(payload['exp']) if payload.get('exp') else None
+    }
+    if socket_users.get(sid) != user_id:
+        _socket_sign_out(keep_session=True)
+        socket_users[sid] = user_id
+        join_room(_user_room(user_id))
+
+        entry = online_users.setdefault(user_id, {'username': user['username'], 'sids': set(), 'watching': None})
+        entry['shares_watching'] = _activity_sharing(user)['watching']
+        came_online = not entry['sids']
+        entry['sids'].add(sid)
+        if came_online:
+            _broadcast_presence(user_id)
+
+    emit('authenticated', {
+        'user_id': user_id,
+        'friends_presence': [_presence_payload(str(f)) for f in _friend_ids(user_id) if str(f) in online_users]
+    })
+
+
+def _socket_sign_out(sid=None, keep_session=False):
+    sid = sid or request.sid
+    user_id = socket_users.pop(sid, None)
+    if not keep_session:
+        socket_sessions.pop(sid, None)
+    if not user_id:
+        return
+    socketio.server.leave_room(sid, _user_room(user_id), namespace='/')
+    entry = online_users.get(user_id)
+    if entry:
+        entry['sids'].discard(sid)
+        if not entry['sids']:
+            del online_users[user_id]
+            _broadcast_presence(user_id)
+
+
+def _disconnect_sockets(user_id=None, session_ids=()):
+    """Sign out and drop the sockets of a user, or of the given sessions, e.g. after they are revoked"""
+    for sid, uid in list(socket_users.items()):
+        if uid == user_id or socket_sessions.get(sid, {}).get('session_id') in session_ids:
+            _socket_sign_out(sid)
+            socketio.server.disconnect(sid, namespace='/')
+
+
+@socketio.on('authenticate')
+def handle_authenticate(data):
+    """Sign an already-connected socket in, e.g. after logging in or refreshing the token"""
+    user, payload = _socket_user_from_token((data or {}).get('token', ''))
+    if not user:
+        emit('auth_error', {'error': 'Invalid or expired token'})
+        return
+    _socket_sign_in(user, payload)
+
+
+@socketio.on('deauthenticate')
+def handle_deauthenticate():
+    _socket_sign_out()
+
+
+@socketio.on('view_content')
+def handle_view_content(data):
+    """Subscribe to comment updates for one title (replaces any previous subscription)"""
+    handle_leave_content()
+    content_id, content_type = (data or {}).get('content_id'), (data or {}).get('content_type')
+    if request.sid not in socket_users or content_id is None or content_type not in ('movie', 'tv'):
+        return
+    room = _content_room(content_type, content_id)
+    socket_content_rooms[request.sid] = room
+    join_room(room)
+
+
+@socketio.on('leave_content')
+def handle_leave_content():
+    room = socket_content_rooms.pop(request.sid, None)
+    if room:
+        leave_room(room)
+
+
+@socketio.on('watching')
+def handle_watching(data):
+    """Set what this user is watching (or clear it with an empty payload) and tell their friends"""
+    user_id = socket_users.get(request.sid)
+    if not user_id or user_id not in online_users:
+        return
+    data = data or {}
+    watching = None
+    if data.get('content_id') is not None and data.get('content_type') in ('movie', 'tv'):
+        watching = {
+            'content_id': data['content_id'],
+            'content_type': data['content_type'],
+            'title': str(data.get('title') or '')[:200],
+            'season': data.get('season') if isinstance(data.get('season'), int) else None,
+            'episode': data.get('episode') if isinstance(data.get('episode'), int) else None,
+            'since': datetime.utcnow().isoformat()
+        }
+    online_users[user_id]['watching'] = watching
+    if online_users[user_id].get('shares_watching'):
+        _broadcast_presence(user_id)
+
 # ============= WATCHPARTY SOCKETIO EVENTS =============
 
 def generate_room_code():
@@ -2257,17 +3771,28 @@ def generate_room_code():
             return code
 
 @socketio.on('connect')
-def handle_connect():
-    """Handle client connection"""
+def handle_connect(auth=None):
+    """Handle client connection. Watchparties work anonymously; a token in auth signs the socket in."""
     print(f"Client connected: {request.sid}")
     emit('connected', {'sid': request.sid})
 
+    token = (auth or {}).get('token') if isinstance(auth, dict) else None
+    if token:
+        user, payload = _socket_user_from_token(token)
+        if user:
+            _socket_sign_in(user, payload)
+        else:
+            emit('auth_error', {'error': 'Invalid or expired token'})
+
 @socketio.on('disconnect')
 def handle_disconnect():
     """Handle client disconnection"""
     sid = request.sid
     print(f"Client disconnected: {sid}")
 
+    _socket_sign_out()
+    socket_content_rooms.pop(sid, None)
+
     # Remove user from their room
     if sid in user_rooms:
         room_code = user_rooms[sid]
@@ -2295,6 +3820,7 @@ def handle_disconnect():
                     # Assign new host (first user)
                     new_host_sid = list(room['users'].keys())[0]
                     room['host'] = new_host_sid
+                    room['host_user_id'] = socket_users.get(new_host_sid)
                     emit('new_host', {
                         'username': room['users'][new_host_sid]
                     }, room=room_code)
@@ -2317,6 +3843,9 @@ def handle_create_party(data):
     # Create room
     watchparty_rooms[room_code] = {
         'host': sid,
+        'host_user_id': socket_users.get(sid),
+        # Stays with the room when hosting passes to someone else (who may be anonymous)
+        'owner_user_id': socket_users.get(sid),
         'users': {sid: username},
         'content': content,
         'state': {
@@ -2356,6 +3885,15 @@ def handle_join_party(data):
 
     room = watchparty_rooms[room_code]
 
+    # Signed-in guests can't join a party whose owner or current host they've blocked
+    # (or been blocked by); anonymous guests are let in as before
+    joiner_id = socket_users.get(sid)
+    if joiner_id:
+        hosts = {room.get('owner_user_id'), room.get('host_user_id')} - {None}
+        if any(_is_blocked_between(host_id, joiner_id) for host_id in hosts):
+            emit('join_error', {'message': 'Room not found'})
+            return
+
     # Add user to room
     room['users'][sid] = username
     user_rooms[sid] = room_code
@@ -2415,6 +3953,7 @@ def handle_leave_party():
                 if room['users']:
                     new_host_sid = list(room['users'].keys())[0]
                     room['host'] = new_host_sid
+                    room['host_user_id'] = socket_users.get(new_host_sid)
                     emit('new_host', {
                         'username': room['users'][new_host_sid]
                     }, room=room_code)
@@ -2948,56 +4487,358 @@ def ngrok_status():
 
 # ============= AUTHENTICATION ROUTES =============
 
-@app.route('/api/auth/register', methods=['POST'])
-def register():
-    try:
-        data = request.get_json()
-        username = data.get('username')
-        email = data.get('email')
-        password = data.get('password')
+# ---------- Sessions & token revocation ----------
+
+def _create_session(user_id):
+    """Record a new login session for the requesting device and return its id"""
+    now = datetime.utcnow()
+    session = {
+        'user_id': ObjectId(user_id),
+        'device': request.headers.get('User-Agent', 'Unknown device')[:256],
+        'ip': _client_ip(),
+        'created_at': now,
+        'last_seen': now,
+        'expires_at': now + app.config['JWT_REFRESH_TOKEN_EXPIRES'],
+        'revoked': False
+    }
+    return sessions_collection.insert_one(session).inserted_id
 
-        if not username or not email or not password:
-            return jsonify({'error': 'Username, email, and password are required'}), 400
 
-        # Check if user already exists
-        if users_collection.find_one({'username': username}):
-            return jsonify({'error': 'Username already exists'}), 400
+def _issue_tokens(user_id, session_id, replacing_jti=None):
+    """
+    Create an access/refresh token pair bound to a session.
+    The refresh token's jti is stored on the session so only the latest one can be used.
+    With replacing_jti the pair is only issued if that is still the session's current refresh
+    token, checked and rotated in one update; returns None otherwise.
+    """
+    claims = {'sid': str(session_id)}
+    access_token = create_access_token(identity=str(user_id), additional_claims=claims)
+    refresh_token = create_refresh_token(identity=str(user_id), additional_claims=claims)
+
+    now = datetime.utcnow()
+    match = {'_id': session_id}
+    if replacing_jti is not None:
+        match.update({'revoked': False, 'refresh_jti': replacing_jti})
+    result = sessions_collection.update_one(
+        match,
+        {'$set': {
+            'refresh_jti': get_jti(refresh_token),
+            'last_seen': now,
+            'expires_at': now + app.config['JWT_REFRESH_TOKEN_EXPIRES']
+        }}
+    )
+    if replacing_jti is not None and result.matched_count == 0:
+        return None
 
-        if users_collection.find_one({'email': email}):
-            return jsonify({'error': 'Email already exists'}), 400
+    return {'access_token': access_token, 'refresh_token': refresh_token}
 
-        # Create new user
-        user = {
-            'username': username,
-            'email': email,
-            'password_hash': generate_password_hash(password),
+
+def _block_token(jwt_payload):
+    """Add a token to the blocklist until it would have expired anyway"""
+    expires_at = datetime.utcfromtimestamp(jwt_payload['exp']) if jwt_payload.get('exp') \
+        else datetime.utcnow() + app.config['JWT_REFRESH_TOKEN_EXPIRES']
+    token_blocklist_collection.update_one(
+        {'jti': jwt_payload['jti']},
+        {'$setOnInsert': {
+            'jti': jwt_payload['jti'],
+            'type': jwt_payload.get('type'),
+            'user_id': jwt_payload.get('sub'),
             'created_at': datetime.utcnow(),
-            'friends': [],
-            'watchlist': [],
-            'continue_watching': [],
-            'favorites': []
-        }
+            'expires_at': expires_at
+        }},
+        upsert=True
+    )
 
-        result = users_collection.insert_one(user)
 
-        # Create access token
-        access_token = create_access_token(identity=str(result.inserted_id))
+def _revoke_sessions(query):
+    """Mark matching sessions revoked; their access and refresh tokens and their sockets stop working immediately"""
+    session_ids = {str(s['_id']) for s in sessions_collection.find({**query, 'revoked': False}, {'_id': 1})}
+    result = sessions_collection.update_many(
+        {**query, 'revoked': False},
+        {'$set': {'revoked': True, 'revoked_at': datetime.utcnow()}}
+    )
+    _disconnect_sockets(session_ids=session_ids)
+    return result
 
-        return jsonify({
-            'message': 'User created successfully',
-            'access_token': access_token,
-            'user': {
-                'id': str(result.inserted_id),
-                'username': username,
-                'email': email
-            }
-        }), 201
 
-    except Exception as e:
-        return jsonify({'error': str(e)}), 500
+@jwt.token_in_blocklist_loader
+def check_if_token_revoked(jwt_header, jwt_payload):
+    """Called by JWTManager on every protected request"""
+    if token_blocklist_collection.find_one({'jti': jwt_payload['jti']}, {'_id': 1}):
+        return True
 
+    sid = jwt_payload.get('sid')
+    if sid:
+        try:
+            session = sessions_collection.find_one({'_id': ObjectId(sid)}, {'revoked': 1})
+        except Exception:
+            return True
+        return not session or session.get('revoked', False)
 
-@app.route('/api/auth/login', methods=['POST'])
+    # Tokens issued before sessions existed carry no sid - honour "sign out everywhere" via the user doc
+    try:
+        user = users_collection.find_one({'_id': ObjectId(jwt_payload['sub'])}, {'tokens_valid_after': 1})
+    except Exception:
+        return False
+    valid_after = user.get('tokens_valid_after') if user else None
+    return bool(valid_after and datetime.utcfromtimestamp(jwt_payload.get('iat', 0)) < valid_after)
+
+
+# ---------- Single-use tokens (email verification, password reset, 2FA login) ----------
+
+EMAIL_VERIFICATION_TTL = timedelta(hours=int(os.environ.get('EMAIL_VERIFICATION_HOURS', 48)))
+PASSWORD_RESET_TTL = timedelta(minutes=int(os.environ.get('PASSWORD_RESET_MINUTES', 60)))
+
+
+def _hash_token(token):
+    return hashlib.sha256(token.encode()).hexdigest()
+
+
+def _create_email_token(user_id, purpose, ttl):
+    """
+    Issue a single-use token for 'verify_email', 'reset_password' or 'login_2fa'.
+    Only the hash is stored, and any earlier unused token for the same purpose is discarded.
+    """
+    email_tokens_collection.delete_many({'user_id': ObjectId(user_id), 'purpose': purpose, 'used_at': None})
+
+    token = secrets.token_urlsafe(32)
+    now = datetime.utcnow()
+    email_tokens_collection.insert_one({
+        'token_hash': _hash_token(token),
+        'user_id': ObjectId(user_id),
+        'purpose': purpose,
+        'created_at': now,
+        'expires_at': now + ttl,
+        'used_at': None
+    })
+    return token
+
+
+def _find_email_token(token, purpose):
+    """Look up a live token without using it up"""
+    return email_tokens_collection.find_one({
+        'token_hash': _hash_token(token),
+        'purpose': purpose,
+        'used_at': None,
+        'expires_at': {'$gt': datetime.utcnow()}
+    })
+
+
+def _consume_email_token(token, purpose):
+    """Atomically mark a token as used; returns None if it is unknown, expired or already used"""
+    now = datetime.utcnow()
+    return email_tokens_collection.find_one_and_update(
+        {'token_hash': _hash_token(token), 'purpose': purpose, 'used_at': None, 'expires_at': {'$gt': now}},
+        {'$set': {'used_at': now}}
+    )
+
+
+def _app_base_url():
+    return (os.environ.get('APP_BASE_URL') or request.host_url).rstrip('/')
+
+
+def _email_base_url():
+    """
+    APP_BASE_URL, or None when it isn't set. Emailed links never fall back to the request's Host
+    header: the client controls it, and the links carry live tokens.
+    """
+    return os.environ.get('APP_BASE_URL', '').strip().rstrip('/') or None
+
+
+def _send_verification_email(user):
+    """Returns True if the email went out"""
+    base_url = _email_base_url()
+    if not base_url:
+        print(f"[Mail] APP_BASE_URL is not set - not sending verification email to {user['email']}")
+        return False
+    token = _create_email_token(user['_id'], 'verify_email', EMAIL_VERIFICATION_TTL)
+    link = f"{base_url}/?verify_email_token={token}"
+    try:
+        mail_sender.send(
+            user['email'],
+            'Verify your GlitchBox email',
+            f"Hi {user['username']},\n\n"
+            f"Confirm your email address by opening this link:\n{link}\n\n"
+            f"The link expires in {int(EMAIL_VERIFICATION_TTL.total_seconds() // 3600)} hours."
+        )
+        return True
+    except Exception as e:
+        print(f"[Mail] Failed to send verification email to {user['email']}: {e}")
+        return False
+
+
+def _send_password_reset_email(user):
+    """Returns True if the email went out"""
+    base_url = _email_base_url()
+    if not base_url:
+        print(f"[Mail] APP_BASE_URL is not set - not sending password reset email to {user['email']}")
+        return False
+    token = _create_email_token(user['_id'], 'reset_password', PASSWORD_RESET_TTL)
+    link = f"{base_url}/?reset_token={token}"
+    try:
+        mail_sender.send(
+            user['email'],
+            'Reset your GlitchBox password',
+            f"Hi {user['username']},\n\n"
+            f"Someone asked to reset your password. If it was you, open this link:\n{link}\n\n"
+            f"The link expires in {int(PASSWORD_RESET_TTL.total_seconds() // 60)} minutes. "
+            f"If you didn't ask for this you can ignore this email."
+        )
+        return True
+    except Exception as e:
+        print(f"[Mail] Failed to send password reset email to {user['email']}: {e}")
+        return False
+
+
+# ---------- TOTP two-factor authentication (RFC 6238) ----------
+
+TOTP_ISSUER = os.environ.get('TOTP_ISSUER', 'GlitchBox')
+TOTP_PERIOD = 30
+TWO_FACTOR_LOGIN_TTL = timedelta(minutes=5)
+RECOVERY_CODE_COUNT = 10
+
+
+def _totp_at(secret, counter):
+    """6-digit HOTP value for a base32 secret and time-step counter"""
+    key = base64.b32decode(secret + '=' * (-len(secret) % 8), casefold=True)
+    digest = hmac.new(key, struct.pack('>Q', counter), hashlib.sha1).digest()
+    offset = digest[-1] & 0x0F
+    value = struct.unpack('>I', digest[offset:offset + 4])[0] & 0x7FFFFFFF
+    return f"{value % 1000000:06d}"
+
+
+def _verify_totp(secret, code, last_counter=None, drift=1):
+    """
+    Check a code against the current time step +/- drift.
+    Returns the matching counter, or None. Counters at or before last_counter are
+    rejected so a code can't be replayed.
+    """
+    current = int(time.time() // TOTP_PERIOD)
+    for counter in range(current - drift, current + drift + 1):
+        if last_counter is not None and counter <= last_counter:
+            continue
+        if hmac.compare_digest(_totp_at(secret, counter), code):
+            return counter
+    return None
+
+
+def _totp_provisioning_uri(secret, username):
+    label = quote(f"{TOTP_ISSUER}:{username}")
+    return f"otpauth://totp/{label}?secret={secret}&issuer={quote(TOTP_ISSUER)}&digits=6&period={TOTP_PERIOD}"
+
+
+def _generate_recovery_codes():
+    """Return (plain codes to show once, hashes to store)"""
+    codes = [f"{secrets.token_hex(4)}-{secrets.token_hex(4)}" for _ in range(RECOVERY_CODE_COUNT)]
+    return codes, [_hash_token(code) for code in codes]
+
+
+def _check_second_factor(user, code=None, recovery_code=None):
+    """Verify a TOTP or recovery code for a 2FA user, burning whichever was used"""
+    if code:
+        counter = _verify_totp(user['totp_secret'], code, user.get('totp_last_counter'))
+        if counter is None:
+            return False
+        # Claim the time step in the same write that checks it, so two logins racing with one code can't both pass
+        result = users_collection.update_one(
+            {'_id': user['_id'], '$or': [{'totp_last_counter': None}, {'totp_last_counter': {'$lt': counter}}]},
+            {'$set': {'totp_last_counter': counter}}
+        )
+        return result.modified_count == 1
+
+    if recovery_code:
+        normalized = recovery_code.strip().lower()
+        result = users_collection.update_one(
+            {'_id': user['_id'], 'totp_recovery_codes': _hash_token(normalized)},
+            {'$pull': {'totp_recovery_codes': _hash_token(normalized)}}
+        )
+        return result.modified_count == 1
+
+    return False
+
+
+def _reauthenticate(user, data):
+    """
+    Check the password (and second factor, for 2FA users) before a sensitive account change.
+    Failures count towards the same per-account lockout as login. Returns an error response, or None.
+    """
+    lock_key = f"account:{user['username'].lower()}"
+    retry_after = rate_limiter.lockout_remaining(lock_key)
+    if retry_after:
+        return _too_many_requests(retry_after, 'Too many failed attempts, please try again later')
+
+    if not check_password_hash(user['password_hash'], data['password']):
+        error = 'Invalid password'
+    elif user.get('totp_enabled') and not _check_second_factor(user, data.get('code'), data.get('recovery_code')):
+        error = 'Invalid two-factor code'
+    else:
+        rate_limiter.reset_login_failures(lock_key)
+        return None
+
+    lock_seconds = rate_limiter.record_login_failure(lock_key)
+    if lock_seconds:
+        return _too_many_requests(lock_seconds, 'Too many failed attempts, please try again later')
+    return jsonify({'error': error}), 401
+
+@app.route('/api/auth/register', methods=['POST'])
+@rate_limit('register')
+@validate_json(REGISTER_SCHEMA, check=_register_checks)
+def register():
+    try:
+        data = request.get_json()
+        username = data.get('username')
+        email = data.get('email')
+        password = data.get('password')
+
+        if not username or not email or not password:
+            return jsonify({'error': 'Username, email, and password are required'}), 400
+
+        # Check if user already exists
+        if users_collection.find_one({'username': username}):
+            return jsonify({'error': 'Username already exists'}), 400
+
+        if users_collection.find_one({'email': email}):
+            return jsonify({'error': 'Email already exists'}), 400
+
+        # Create new user
+        user = {
+            'username': username,
+            'email': email,
+            'password_hash': generate_password_hash(password),
+            'email_verified': False,
+            'role': 'user',
+            'status': 'active',
+            'created_at': datetime.utcnow(),
+            'favorites': []
+        }
+
+        result = users_collection.insert_one(user)
+        user['_id'] = result.inserted_id
+        _send_verification_email(user)
+
+        # Start a session and issue the access/refresh pair
+        session_id = _create_session(result.inserted_id)
+        tokens = _issue_tokens(result.inserted_id, session_id)
+
+        return jsonify({
+            'message': 'User created successfully',
+            'access_token': tokens['access_token'],
+            'refresh_token': tokens['refresh_token'],
+            'user': {
+                'id': str(result.inserted_id),
+                'username': username,
+                'email': email,
+                'email_verified': False
+            }
+        }), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/auth/login', methods=['POST'])
+@rate_limit('login', per_account=_login_account)
+@validate_json(LOGIN_SCHEMA)
 def login():
     try:
         data = request.get_json()
@@ -3007,20 +4848,50 @@ def login():
         if not username or not password:
             return jsonify({'error': 'Username and password are required'}), 400
 
+        # Progressive lockout is tracked per account and per IP
+        lock_keys = [f"account:{username.lower()}", f"ip:{_client_ip()}"]
+        retry_after = max(filter(None, (rate_limiter.lockout_remaining(k) for k in lock_keys)), default=None)
+        if retry_after:
+            return _too_many_requests(retry_after, 'Too many failed login attempts, please try again later')
+
         user = users_collection.find_one({'username': username})
 
         if not user or not check_password_hash(user['password_hash'], password):
+            lock_seconds = max(filter(None, (rate_limiter.record_login_failure(k) for k in lock_keys)), default=None)
+            if lock_seconds:
+                return _too_many_requests(lock_seconds, 'Too many failed login attempts, please try again later')
             return jsonify({'error': 'Invalid username or password'}), 401
 
-        access_token = create_access_token(identity=str(user['_id']))
+        block_reason = _account_block_reason(user)
+        if block_reason:
+            return jsonify({'error': block_reason}), 403
+
+        if user.get('totp_enabled'):
+            # Password was right; the session is only created once the second factor checks out
+            two_factor_token = _create_email_token(user['_id'], 'login_2fa', TWO_FACTOR_LOGIN_TTL)
+            return jsonify({
+                'message': 'Two-factor code required',
+                'two_factor_required': True,
+                'two_factor_token': two_factor_token
+            }), 200
+
+        # A successful login clears the IP's count too, so people sharing an address with
+        # someone who mistypes aren't locked out; the per-account count still stops guessing
+        for key in lock_keys:
+            rate_limiter.reset_login_failures(key)
+
+        session_id = _create_session(user['_id'])
+        tokens = _issue_tokens(user['_id'], session_id)
 
         return jsonify({
             'message': 'Login successful',
-            'access_token': access_token,
+            'access_token': tokens['access_token'],
+            'refresh_token': tokens['refresh_token'],
             'user': {
                 'id': str(user['_id']),
                 'username': user['username'],
-                'email': user['email']
+                'email': user['email'],
+                'email_verified': user.get('email_verified', False)
             }
         }), 200
 
@@ -3028,32 +4899,66 @@ def login():
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/auth/me', methods=['GET'])
-@jwt_required()
-def get_current_user():
+@app.route('/api/auth/login/2fa', methods=['POST'])
+@rate_limit('login')
+@validate_json(TWO_FACTOR_LOGIN_SCHEMA, check=_second_factor_checks)
+def login_two_factor():
     try:
-        user_id = get_jwt_identity()
-        user = users_collection.find_one({'_id': ObjectId(user_id)})
+        data = request.get_json()
 
-        if not user:
-            return jsonify({'error': 'User not found'}), 404
+        token_doc = _find_email_token(data['two_factor_token'], 'login_2fa')
+        if not token_doc:
+            return jsonify({'error': 'Login attempt expired, please log in again'}), 401
+
+        user = users_collection.find_one({'_id': token_doc['user_id']})
+        if not user or not user.get('totp_enabled'):
+            return jsonify({'error': 'Login attempt expired, please log in again'}), 401
+
+        lock_keys = [f"account:{user['username'].lower()}", f"ip:{_client_ip()}"]
+        retry_after = max(filter(None, (rate_limiter.lockout_remaining(k) for k in lock_keys)), default=None)
+        if retry_after:
+            return _too_many_requests(retry_after, 'Too many failed login attempts, please try again later')
+
+        if not _check_second_factor(user, data.get('code'), data.get('recovery_code')):
+            lock_seconds = max(filter(None, (rate_limiter.record_login_failure(k) for k in lock_keys)), default=None)
+            if lock_seconds:
+                return _too_many_requests(lock_seconds, 'Too many failed login attempts, please try again later')
+            return jsonify({'error': 'Invalid two-factor code'}), 401
+
+        block_reason = _account_block_reason(user)
+        if block_reason:
+            return jsonify({'error': block_reason}), 403
+
+        if not _consume_email_token(data['two_factor_token'], 'login_2fa'):
+            return jsonify({'error': 'Login attempt expired, please log in again'}), 401
+
+        for key in lock_keys:
+            rate_limiter.reset_login_failures(key)
+
+        session_id = _create_session(user['_id'])
+        tokens = _issue_tokens(user['_id'], session_id)
+        remaining_codes = len(users_collection.find_one({'_id': user['_id']}, {'totp_recovery_codes': 1}).get('totp_recovery_codes', []))
 
         return jsonify({
-            'id': str(user['_id']),
-            'username': user['username'],
-            'email': user['email'],
-            'friends': [str(f) for f in user.get('friends', [])]
+            'message': 'Login successful',
+            'access_token': tokens['access_token'],
+            'refresh_token': tokens['refresh_token'],
+            'recovery_codes_remaining': remaining_codes,
+            'user': {
+                'id': str(user['_id']),
+                'username': user['username'],
+                'email': user['email'],
+                'email_verified': user.get('email_verified', False)
+            }
         }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-# ============= WATCHLIST ROUTES =============
-
-@app.route('/api/watchlist', methods=['GET'])
+@app.route('/api/auth/2fa/setup', methods=['POST'])
 @jwt_required()
-def get_watchlist():
+def setup_two_factor():
     try:
         user_id = get_jwt_identity()
         user = users_collection.find_one({'_id': ObjectId(user_id)})
@@ -3061,256 +4966,265 @@ def get_watchlist():
         if not user:
             return jsonify({'error': 'User not found'}), 404
 
-        # Get watchlist from user document (default to empty array)
-        watchlist_items = user.get('watchlist', [])
+        if user.get('totp_enabled'):
+            return jsonify({'error': 'Two-factor authentication is already enabled'}), 400
 
-        return jsonify(watchlist_items), 200
+        # Stays pending until the first code is confirmed via /api/auth/2fa/enable
+        secret = base64.b32encode(secrets.token_bytes(20)).decode().rstrip('=')
+        users_collection.update_one({'_id': user['_id']}, {'$set': {'totp_pending_secret': secret}})
+
+        return jsonify({
+            'secret': secret,
+            'provisioning_uri': _totp_provisioning_uri(secret, user['username'])
+        }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/watchlist', methods=['POST'])
+@app.route('/api/auth/2fa/enable', methods=['POST'])
 @jwt_required()
-def add_to_watchlist():
+@validate_json(TWO_FACTOR_ENABLE_SCHEMA)
+def enable_two_factor():
     try:
         user_id = get_jwt_identity()
         data = request.get_json()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
 
-        # Get list name (default to "My Watchlist" for backwards compatibility)
-        list_name = data.get('list_name', 'My Watchlist')
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
 
-        watchlist_item = {
-            'content_id': data['content_id'],
-            'content_type': data['content_type'],
-            'title': data['title'],
-            'poster_path': data.get('poster_path'),
-            'list_name': list_name,
-            'added_at': datetime.utcnow()
-        }
+        secret = user.get('totp_pending_secret')
+        if not secret:
+            return jsonify({'error': 'Start two-factor setup first'}), 400
 
-        # First ensure the user has a watchlist array
-        users_collection.update_one(
-            {'_id': ObjectId(user_id), 'watchlist': {'$exists': False}},
-            {'$set': {'watchlist': []}}
-        )
+        counter = _verify_totp(secret, data['code'])
+        if counter is None:
+            return jsonify({'error': 'Invalid two-factor code'}), 400
 
-        # Check if already in watchlist and update if so
-        result = users_collection.update_one(
-            {
-                '_id': ObjectId(user_id),
-                'watchlist': {
-                    '$not': {
-                        '$elemMatch': {
-                            'content_id': data['content_id'],
-                            'list_name': list_name
-                        }
-                    }
-                }
-            },
+        codes, code_hashes = _generate_recovery_codes()
+        users_collection.update_one(
+            {'_id': user['_id']},
             {
-                '$push': {'watchlist': watchlist_item}
+                '$set': {
+                    'totp_enabled': True,
+                    'totp_secret': secret,
+                    'totp_last_counter': counter,
+                    'totp_recovery_codes': code_hashes,
+                    'totp_enabled_at': datetime.utcnow()
+                },
+                '$unset': {'totp_pending_secret': ''}
             }
         )
 
-        if result.matched_count == 0:
-            # Either user not found or item already exists
-            user = users_collection.find_one({'_id': ObjectId(user_id)})
-            if not user:
-                return jsonify({'error': 'User not found'}), 404
-            return jsonify({'error': 'Item already in watchlist'}), 400
-
-        return jsonify(watchlist_item), 201
+        return jsonify({
+            'message': 'Two-factor authentication enabled',
+            'recovery_codes': codes
+        }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/watchlist/<content_id>', methods=['DELETE'])
+@app.route('/api/auth/2fa/disable', methods=['POST'])
 @jwt_required()
-def remove_from_watchlist(content_id):
+@rate_limit('login', per_account=get_jwt_identity)
+@validate_json(TWO_FACTOR_DISABLE_SCHEMA, check=_second_factor_checks)
+def disable_two_factor():
     try:
         user_id = get_jwt_identity()
+        data = request.get_json()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
 
-        # Try to convert content_id to int if possible (TMDB IDs are integers)
-        try:
-            content_id_int = int(content_id)
-        except ValueError:
-            content_id_int = None
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
 
-        # Remove item from watchlist array (try both string and int versions)
-        result = users_collection.update_one(
-            {'_id': ObjectId(user_id)},
+        if not user.get('totp_enabled'):
+            return jsonify({'error': 'Two-factor authentication is not enabled'}), 400
+
+        # Re-authenticate with both factors before turning protection off
+        error = _reauthenticate(user, data)
+        if error:
+            return error
+
+        users_collection.update_one(
+            {'_id': user['_id']},
             {
-                '$pull': {
-                    'watchlist': {
-                        'content_id': {'$in': [content_id, content_id_int] if content_id_int else [content_id]}
-                    }
+                '$set': {'totp_enabled': False},
+                '$unset': {
+                    'totp_secret': '',
+                    'totp_last_counter': '',
+                    'totp_recovery_codes': '',
+                    'totp_enabled_at': ''
                 }
             }
         )
 
-        if result.matched_count == 0:
-            return jsonify({'error': 'User not found'}), 404
-
-        # Don't fail if item wasn't found - it might already be deleted
-        return jsonify({'message': 'Removed from watchlist'}), 200
+        return jsonify({'message': 'Two-factor authentication disabled'}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/watchlist/rename', methods=['PUT'])
+@app.route('/api/auth/me', methods=['GET'])
 @jwt_required()
-def rename_watchlist():
+def get_current_user():
     try:
         user_id = get_jwt_identity()
-        data = request.get_json()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
 
-        old_name = data.get('old_name')
-        new_name = data.get('new_name')
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
 
-        if not old_name or not new_name:
-            return jsonify({'error': 'old_name and new_name are required'}), 400
+        return jsonify({
+            'id': str(user['_id']),
+            'username': user['username'],
+            'email': user['email'],
+            'email_verified': user.get('email_verified', False),
+            'two_factor_enabled': user.get('totp_enabled', False),
+            'role': _user_role(user),
+            'profile': _profile_response(user),
+            'friends': [str(f) for f in _friend_ids(user['_id'])]
+        }), 200
 
-        if len(new_name) > 50:
-            return jsonify({'error': 'List name must be 50 characters or less'}), 400
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-        # Update all watchlist items with the old list_name to the new list_name
-        result = users_collection.update_one(
-            {'_id': ObjectId(user_id)},
-            {
-                '$set': {
-                    'watchlist.$[elem].list_name': new_name
-                }
-            },
-            array_filters=[{'elem.list_name': old_name}]
-        )
 
-        if result.matched_count == 0:
-            return jsonify({'error': 'User not found'}), 404
+@app.route('/api/auth/refresh', methods=['POST'])
+@jwt_required(refresh=True)
+def refresh_tokens():
+    try:
+        payload = get_jwt()
+        sid = payload.get('sid')
+        session = sessions_collection.find_one({'_id': ObjectId(sid), 'revoked': False}) if sid else None
 
-        return jsonify({'message': f'Renamed list from "{old_name}" to "{new_name}"'}), 200
+        if not session:
+            return jsonify({'error': 'Session expired, please log in again'}), 401
+
+        # Only one request can swap out a given refresh token, even if two arrive together
+        tokens = _issue_tokens(payload['sub'], session['_id'], replacing_jti=payload['jti'])
+        if not tokens:
+            # An already-rotated refresh token was replayed - assume it leaked and end the session
+            _revoke_sessions({'_id': session['_id']})
+            return jsonify({'error': 'Session expired, please log in again'}), 401
+
+        sessions_collection.update_one({'_id': session['_id']}, {'$set': {'ip': _client_ip()}})
+        return jsonify(tokens), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-# ============= CONTINUE WATCHING ROUTES =============
-
-@app.route('/api/continue-watching', methods=['GET'])
+@app.route('/api/auth/logout', methods=['POST'])
 @jwt_required()
-def get_continue_watching():
+def logout():
     try:
-        user_id = get_jwt_identity()
-        user = users_collection.find_one({'_id': ObjectId(user_id)})
+        payload = get_jwt()
+        _block_token(payload)
+        if payload.get('sid'):
+            _revoke_sessions({'_id': ObjectId(payload['sid'])})
 
-        if not user:
-            return jsonify({'error': 'User not found'}), 404
-
-        # Get continue watching from user document (default to empty array)
-        items = user.get('continue_watching', [])
-
-        # Sort by last_watched in descending order
-        items = sorted(items, key=lambda x: x.get('last_watched', datetime.min), reverse=True)
-
-        return jsonify(items), 200
+        return jsonify({'message': 'Logged out'}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/continue-watching', methods=['POST'])
+@app.route('/api/auth/logout-all', methods=['POST'])
 @jwt_required()
-def update_continue_watching():
+def logout_all():
     try:
         user_id = get_jwt_identity()
-        data = request.get_json()
-
-        continue_watching_item = {
-            'content_id': data['content_id'],
-            'content_type': data['content_type'],
-            'title': data['title'],
-            'poster_path': data.get('poster_path'),
-            'progress': data.get('progress', 0),
-            'season': data.get('season'),
-            'episode': data.get('episode'),
-            'last_watched': datetime.utcnow()
-        }
+        payload = get_jwt()
 
-        # First ensure the user has a continue_watching array
+        result = _revoke_sessions({'user_id': ObjectId(user_id)})
         users_collection.update_one(
-            {'_id': ObjectId(user_id), 'continue_watching': {'$exists': False}},
-            {'$set': {'continue_watching': []}}
-        )
-
-        # Update existing item or add new one
-        result = users_collection.update_one(
-            {
-                '_id': ObjectId(user_id),
-                'continue_watching.content_id': data['content_id']
-            },
-            {
-                '$set': {
-                    'continue_watching.$': continue_watching_item
-                }
-            }
+            {'_id': ObjectId(user_id)},
+            {'$set': {'tokens_valid_after': datetime.utcnow()}}
         )
+        _block_token(payload)
+        _disconnect_sockets(user_id=user_id)
 
-        # If no existing item was updated, add a new one
-        if result.matched_count == 0:
-            users_collection.update_one(
-                {'_id': ObjectId(user_id)},
-                {
-                    '$push': {'continue_watching': continue_watching_item}
-                }
-            )
-
-        return jsonify({'message': 'Continue watching updated'}), 200
+        return jsonify({'message': 'Signed out everywhere', 'revoked_sessions': result.modified_count}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/continue-watching/<content_id>', methods=['DELETE'])
+@app.route('/api/auth/sessions', methods=['GET'])
 @jwt_required()
-def remove_from_continue_watching(content_id):
+def get_sessions():
     try:
         user_id = get_jwt_identity()
+        current_sid = get_jwt().get('sid')
 
-        # Try to convert content_id to int if possible (TMDB IDs are integers)
-        try:
-            content_id_int = int(content_id)
-        except ValueError:
-            content_id_int = None
+        sessions = sessions_collection.find({
+            'user_id': ObjectId(user_id),
+            'revoked': False,
+            'expires_at': {'$gt': datetime.utcnow()}
+        }).sort('last_seen', -1)
+
+        result = [{
+            'id': str(s['_id']),
+            'device': s.get('device'),
+            'ip': s.get('ip'),
+            'created_at': s['created_at'].isoformat(),
+            'last_seen': s['last_seen'].isoformat(),
+            'current': str(s['_id']) == current_sid
+        } for s in sessions]
 
-        # Remove item from continue_watching array (try both string and int versions)
-        result = users_collection.update_one(
-            {'_id': ObjectId(user_id)},
-            {
-                '$pull': {
-                    'continue_watching': {
-                        'content_id': {'$in': [content_id, content_id_int] if content_id_int else [content_id]}
-                    }
-                }
-            }
-        )
+        return jsonify(result), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/auth/sessions/<session_id>', methods=['DELETE'])
+@jwt_required()
+def revoke_session(session_id):
+    try:
+        user_id = get_jwt_identity()
+
+        result = _revoke_sessions({'_id': ObjectId(session_id), 'user_id': ObjectId(user_id)})
 
         if result.matched_count == 0:
-            return jsonify({'error': 'User not found'}), 404
+            return jsonify({'error': 'Session not found'}), 404
 
-        # Don't fail if item wasn't found - it might already be deleted
-        return jsonify({'message': 'Removed from continue watching'}), 200
+        return jsonify({'message': 'Session revoked'}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-# ============= FAVORITES (LIVE CHANNELS) ROUTES =============
+@app.route('/api/auth/verify-email', methods=['POST'])
+@validate_json(TOKEN_SCHEMA)
+def verify_email():
+    try:
+        data = request.get_json(silent=True) or {}
+        token = data.get('token')
 
-@app.route('/api/favorites', methods=['GET'])
+        if not token:
+            return jsonify({'error': 'Token is required'}), 400
+
+        token_doc = _consume_email_token(token, 'verify_email')
+        if not token_doc:
+            return jsonify({'error': 'Verification link is invalid or has expired'}), 400
+
+        users_collection.update_one(
+            {'_id': token_doc['user_id']},
+            {'$set': {'email_verified': True, 'email_verified_at': datetime.utcnow()}}
+        )
+
+        return jsonify({'message': 'Email verified'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/auth/resend-verification', methods=['POST'])
 @jwt_required()
-def get_favorites():
+def resend_verification():
     try:
         user_id = get_jwt_identity()
         user = users_collection.find_one({'_id': ObjectId(user_id)})
@@ -3318,616 +5232,4920 @@ def get_favorites():
         if not user:
             return jsonify({'error': 'User not found'}), 404
 
-        # Get favorites from user document (default to empty array)
-        favorites = user.get('favorites', [])
+        if user.get('email_verified'):
+            return jsonify({'error': 'Email already verified'}), 400
 
-        return jsonify(favorites), 200
+        if not _send_verification_email(user):
+            return jsonify({'error': 'Could not send the verification email. Please try again later.'}), 503
+
+        return jsonify({'message': 'Verification email sent'}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/favorites', methods=['POST'])
-@jwt_required()
-def add_to_favorites():
+@app.route('/api/auth/forgot-password', methods=['POST'])
+@validate_json(FORGOT_PASSWORD_SCHEMA)
+def forgot_password():
     try:
-        user_id = get_jwt_identity()
-        data = request.get_json()
+        data = request.get_json(silent=True) or {}
+        email = data.get('email')
 
-        favorite_item = {
-            'channel_id': data['channel_id'],
-            'channel_name': data['channel_name'],
-            'added_at': datetime.utcnow()
-        }
+        if not email:
+            return jsonify({'error': 'Email is required'}), 400
 
-        # First ensure the user has a favorites array
-        users_collection.update_one(
-            {'_id': ObjectId(user_id), 'favorites': {'$exists': False}},
-            {'$set': {'favorites': []}}
-        )
+        user = users_collection.find_one({'email': email})
+        if user:
+            _send_password_reset_email(user)
 
-        # Check if already in favorites and add if not
-        result = users_collection.update_one(
-            {
-                '_id': ObjectId(user_id),
-                'favorites.channel_id': {'$ne': data['channel_id']}
-            },
-            {
-                '$push': {'favorites': favorite_item}
-            }
+        # Same response either way so this can't be used to probe for accounts
+        return jsonify({'message': 'If that email is registered, a reset link has been sent'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/auth/reset-password', methods=['POST'])
+@validate_json(RESET_PASSWORD_SCHEMA, check=_reset_password_checks)
+def reset_password():
+    try:
+        data = request.get_json(silent=True) or {}
+        token = data.get('token')
+        password = data.get('password')
+
+        if not token or not password:
+            return jsonify({'error': 'Token and password are required'}), 400
+
+        token_doc = _consume_email_token(token, 'reset_password')
+        if not token_doc:
+            return jsonify({'error': 'Reset link is invalid or has expired'}), 400
+
+        now = datetime.utcnow()
+        users_collection.update_one(
+            {'_id': token_doc['user_id']},
+            {'$set': {
+                'password_hash': generate_password_hash(password),
+                'password_changed_at': now,
+                'tokens_valid_after': now,
+                # The reset link arrived by email, so the address is proven
+                'email_verified': True
+            }}
         )
 
-        if result.matched_count == 0:
-            # Either user not found or channel already in favorites
-            user = users_collection.find_one({'_id': ObjectId(user_id)})
-            if not user:
-                return jsonify({'error': 'User not found'}), 404
-            return jsonify({'error': 'Channel already in favorites'}), 400
+        # Sign the account out everywhere in case the old password was compromised
+        _revoke_sessions({'user_id': token_doc['user_id']})
 
-        return jsonify(favorite_item), 201
+        return jsonify({'message': 'Password has been reset, please log in'}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/favorites/<path:channel_id>', methods=['DELETE'])
+# ============= ACCOUNT ROUTES (EXPORT & DELETION) =============
+
+# Fields on the users document that never leave the server, even in an export
+PRIVATE_USER_FIELDS = ('password_hash', 'totp_secret', 'totp_pending_secret', 'totp_recovery_codes', 'totp_last_counter', 'calendar_token')
+
+
+def _json_safe(value):
+    """Recursively convert ObjectIds and datetimes so a Mongo document can be JSON-encoded"""
+    if isinstance(value, ObjectId):
+        return str(value)
+    if isinstance(value, datetime):
+        return value.isoformat()
+    if isinstance(value, dict):
+        return {k: _json_safe(v) for k, v in value.items()}
+    if isinstance(value, (list, tuple)):
+        return [_json_safe(v) for v in value]
+    return value
+
+
+def _export_collaborative_list(lst, user_id):
+    """A list the user collaborates on, without its share link or anyone else's ids"""
+    lst = {k: v for k, v in lst.items() if k not in ('share_token', 'collaborators')}
+    lst['items'] = [{**item, 'added_by': item['added_by'] if item.get('added_by') == user_id else None}
+                    for item in lst.get('items', [])]
+    return lst
+
+
+def _collect_user_data(user):
+    """Everything stored about a user, grouped by collection"""
+    user_id = user['_id']
+    profile = {k: v for k, v in user.items() if k not in PRIVATE_USER_FIELDS}
+
+    return _json_safe({
+        'exported_at': datetime.utcnow(),
+        'user': profile,
+        'comments': list(comments_collection.find({'user_id': user_id}).sort('created_at', 1)),
+        'comment_likes': list(comment_likes_collection.find({'user_id': user_id})),
+        'comment_revisions': list(comment_revisions_collection.find({'user_id': user_id})),
+        'comment_reactions': list(comment_reactions_collection.find({'user_id': user_id})),
+        'notifications': list(notifications_collection.find({'user_id': user_id})),
+        'comment_reports': list(comment_reports_collection.find({'reporter_id': user_id}, {'reporter_id': 0})),
+        'warnings': list(user_warnings_collection.find({'user_id': user_id}, {'moderator_id': 0})),
+        'ratings': list(ratings_collection.find({'user_id': user_id})),
+        'review_helpful_votes': list(review_helpful_collection.find({'user_id': user_id})),
+        'lists': list(lists_collection.find({'owner_id': user_id})),
+        'collaborative_lists': [_export_collaborative_list(lst, user_id)
+                                for lst in lists_collection.find({'collaborators': user_id})],
+        'list_imports': list(list_imports_collection.find({'user_id': user_id})),
+        'watch_history': list(watch_history_collection.find({'user_id': user_id}).sort('last_watched_at', 1)),
+        'show_tracking': list(show_tracking_collection.find({'user_id': user_id})),
+        'friend_requests': list(friend_requests_collection.find({
+            '$or': [{'from_user_id': user_id}, {'to_user_id': user_id}]
+        })),
+        'sessions': list(sessions_collection.find({'user_id': user_id}, {'refresh_jti': 0}))
+    })
+
+
+@app.route('/api/account/export', methods=['GET'])
 @jwt_required()
-def remove_from_favorites(channel_id):
+def export_account():
+    """
+    Download all personal data.
+    ?format=json (default) returns one JSON document; ?format=zip returns one JSON file per collection.
+    """
     try:
         user_id = get_jwt_identity()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
 
-        # Remove channel from favorites array
-        result = users_collection.update_one(
-            {'_id': ObjectId(user_id)},
-            {
-                '$pull': {
-                    'favorites': {'channel_id': channel_id}
-                }
-            }
-        )
-
-        if result.matched_count == 0:
+        if not user:
             return jsonify({'error': 'User not found'}), 404
 
-        if result.modified_count == 0:
-            return jsonify({'error': 'Channel not found in favorites'}), 404
+        export = _collect_user_data(user)
+        export_format = request.args.get('format', 'json').lower()
+        filename = f"glitchbox-export-{user['username']}-{datetime.utcnow().strftime('%Y%m%d')}"
 
-        return jsonify({'message': 'Removed from favorites'}), 200
+        if export_format == 'zip':
+            buffer = BytesIO()
+            with zipfile.ZipFile(buffer, 'w', zipfile.ZIP_DEFLATED) as archive:
+                for section, content in export.items():
+                    archive.writestr(f"{section}.json", json.dumps(content, indent=2))
+            buffer.seek(0)
+            return send_file(buffer, mimetype='application/zip', as_attachment=True, download_name=f"{filename}.zip")
+
+        if export_format != 'json':
+            return jsonify({'error': 'format must be json or zip'}), 400
+
+        response = jsonify(export)
+        response.headers['Content-Disposition'] = f'attachment; filename="{filename}.json"'
+        return response, 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
-@app.route('/api/favorites/remove', methods=['POST'])
+
+def _delete_user_comments(user_id):
+    """
+    Remove a user's comments. Comments other people replied to are anonymized
+    instead so their threads stay intact; the rest are deleted with their likes.
+    """
+    now = datetime.utcnow()
+    remaining = {c['_id'] for c in comments_collection.find({'user_id': user_id}, {'_id': 1})}
+    comments_collection.update_many(
+        {'user_id': user_id},
+        {
+            '$set': {
+                'user_id': None,
+                'username': '[deleted]',
+                'comment_text': '[deleted]',
+                'deleted': True,
+                'deleted_at': now,
+                'deleted_reason': 'account_deleted'
+            }
+        }
+    )
+
+    # Repeatedly prune this user's comments that have no replies (a pruned reply can free its parent)
+    removed = 0
+    while remaining:
+        replied_to = set(comments_collection.distinct('parent_comment_id', {'parent_comment_id': {'$in': list(remaining)}}))
+        orphans = list(remaining - replied_to)
+        if not orphans:
+            break
+        comments_collection.delete_many({'_id': {'$in': orphans}})
+        comment_likes_collection.delete_many({'comment_id': {'$in': orphans}})
+        comment_reactions_collection.delete_many({'comment_id': {'$in': orphans}})
+        remaining = replied_to
+        removed += len(orphans)
+    return removed
+
+
+@app.route('/api/account', methods=['DELETE'])
 @jwt_required()
-def remove_from_favorites_post():
+@rate_limit('login', per_account=get_jwt_identity)
+@validate_json(ACCOUNT_DELETE_SCHEMA)
+def delete_account():
     try:
-        user_id = get_jwt_identity()
+        user_id = ObjectId(get_jwt_identity())
         data = request.get_json()
-        channel_id = data.get('channel_id')
+        user = users_collection.find_one({'_id': user_id})
 
-        if not channel_id:
-            return jsonify({'error': 'channel_id is required'}), 400
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
 
-        result = users_collection.update_one(
-            {'_id': ObjectId(user_id)},
-            {
-                '$pull': {
-                    'favorites': {'channel_id': channel_id}
-                }
-            }
+        # Re-authenticate before doing anything irreversible
+        error = _reauthenticate(user, data)
+        if error:
+            return error
+
+        removed_comments = _delete_user_comments(user_id)
+        removed_likes = comment_likes_collection.delete_many({'user_id': user_id}).deleted_count
+        comment_revisions_collection.delete_many({'user_id': user_id})
+        comment_reactions_collection.delete_many({'user_id': user_id})
+        notifications_collection.delete_many({'$or': [{'user_id': user_id}, {'actor_id': user_id}]})
+        comment_reports_collection.delete_many({'reporter_id': user_id})
+        user_warnings_collection.delete_many({'user_id': user_id})
+        own_reviews = [r['_id'] for r in ratings_collection.find({'user_id': user_id, 'review': {'$exists': True}}, {'_id': 1})]
+        for vote in review_helpful_collection.find({'user_id': user_id}, {'review_id': 1}):
+            ratings_collection.update_one({'_id': vote['review_id'], 'review': {'$exists': True}}, {'$inc': {'review.helpful_count': -1}})
+        review_helpful_collection.delete_many({'$or': [{'user_id': user_id}, {'review_id': {'$in': own_reviews}}]})
+        removed_ratings = ratings_collection.delete_many({'user_id': user_id}).deleted_count
+        removed_requests = friend_requests_collection.delete_many({
+            '$or': [{'from_user_id': user_id}, {'to_user_id': user_id}]
+        }).deleted_count
+        users_collection.update_many(
+            {'$or': [{'blocked_users': user_id}, {'muted_users': user_id}]},
+            {'$pull': {'blocked_users': user_id, 'muted_users': user_id}}
         )
+        removed_lists = lists_collection.delete_many({'owner_id': user_id}).deleted_count
+        removed_history = watch_history_collection.delete_many({'user_id': user_id}).deleted_count
+        show_tracking_collection.delete_many({'user_id': user_id})
+        lists_collection.update_many({'collaborators': user_id}, {'$pull': {'collaborators': user_id}})
+        list_imports_collection.delete_many({'user_id': user_id})
 
-        if result.matched_count == 0:
-            return jsonify({'error': 'User not found'}), 404
+        sessions_collection.delete_many({'user_id': user_id})
+        _disconnect_sockets(user_id=str(user_id))
+        email_tokens_collection.delete_many({'user_id': user_id})
+        _remove_avatar_file((user.get('profile') or {}).get('avatar'))
+        _block_token(get_jwt())
+        users_collection.delete_one({'_id': user_id})
 
-        if result.modified_count == 0:
-            return jsonify({'error': 'Channel not found in favorites'}), 404
+        print(f"[Account] Deleted account {user['username']} ({user_id})")
 
-        return jsonify({'message': 'Removed from favorites'}), 200
+        return jsonify({
+            'message': 'Account deleted',
+            'removed': {
+                'comments': removed_comments,
+                'comment_likes': removed_likes,
+                'ratings': removed_ratings,
+                'friend_requests': removed_requests,
+                'lists': removed_lists,
+                'watch_history': removed_history
+            }
+        }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-# ============= FRIENDS ROUTES =============
+# ============= PROFILE ROUTES =============
 
-@app.route('/api/friends', methods=['GET'])
+def _avatar_url(profile):
+    return f"/api/users/avatars/{profile['avatar']}" if profile.get('avatar') else None
+
+
+def _profile_response(user, full=True):
+    """
+    Public-facing profile. With full=False only the name card is returned
+    (used when visibility hides the rest from the viewer).
+    """
+    profile = user.get('profile') or {}
+    result = {
+        'id': str(user['_id']),
+        'username': user['username'],
+        'display_name': profile.get('display_name') or user['username'],
+        'avatar_url': _avatar_url(profile),
+        'visibility': profile.get('visibility', 'public')
+    }
+    if full:
+        result.update({
+            'bio': profile.get('bio', ''),
+            'favorite_genres': profile.get('favorite_genres', []),
+            'member_since': user['created_at'].isoformat() if user.get('created_at') else None
+        })
+    return result
+
+
+def _profile_access(user, viewer_id):
+    """(can see the full profile, is self, is friend) for a viewer id or None"""
+    is_self = viewer_id is not None and str(user['_id']) == viewer_id
+    is_friend = viewer_id is not None and not is_self and _are_friends(user['_id'], viewer_id)
+    visibility = (user.get('profile') or {}).get('visibility', 'public')
+    can_see = is_self or visibility == 'public' or (visibility == 'friends' and is_friend)
+    return can_see, is_self, is_friend
+
+
+@app.route('/api/profile', methods=['GET'])
 @jwt_required()
-def get_friends():
+def get_profile():
     try:
         user_id = get_jwt_identity()
-        print(f"[Friends] get_friends called for user_id: {user_id}")
         user = users_collection.find_one({'_id': ObjectId(user_id)})
 
         if not user:
-            print(f"[Friends] User not found: {user_id}")
             return jsonify({'error': 'User not found'}), 404
 
-        friend_ids = user.get('friends', [])
-        if friend_ids is None:
-            friend_ids = []
-        print(f"[Friends] User {user.get('username')} has friend_ids: {friend_ids}")
-        print(f"[Friends] Type of friend_ids: {type(friend_ids)}")
-        print(f"[Friends] Is list: {isinstance(friend_ids, list)}")
-        print(f"[Friends] Length: {len(friend_ids)}")
-
-        if friend_ids:
-            print(f"[Friends] First friend_id: {friend_ids[0]}")
-            print(f"[Friends] First friend_id type: {type(friend_ids[0])}")
-
-            # Ensure all friend_ids are ObjectIds
-            if not isinstance(friend_ids[0], ObjectId):
-                print(f"[Friends] Converting friend_ids to ObjectIds")
-                friend_ids = [ObjectId(fid) if not isinstance(fid, ObjectId) else fid for fid in friend_ids]
-                print(f"[Friends] After conversion: {friend_ids}")
-
-        print(f"[Friends] Querying for friends with _id in: {friend_ids}")
-        friends = list(users_collection.find({'_id': {'$in': friend_ids}}))
-        print(f"[Friends] Found {len(friends)} friend documents")
-        if friends:
-            print(f"[Friends] Friend usernames: {[f.get('username') for f in friends]}")
-
-        friends_list = [{
-            'id': str(friend['_id']),
-            'username': friend['username']
-        } for friend in friends]
-
-        print(f"[Friends] Returning friends: {[f['username'] for f in friends_list]}")
-        return jsonify(friends_list), 200
+        return jsonify(_profile_response(user)), 200
 
     except Exception as e:
-        print(f"[Friends] Error in get_friends: {str(e)}")
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/friends/search', methods=['GET'])
+@app.route('/api/profile', methods=['PUT'])
 @jwt_required()
-def search_users():
+@validate_json(PROFILE_SCHEMA)
+def update_profile():
     try:
-        query = request.args.get('q', '')
         user_id = get_jwt_identity()
+        data = request.get_json()
 
-        print(f"[Friends] Search request - Query: '{query}', User ID: {user_id}")
+        updates = {}
+        for field in ('display_name', 'bio'):
+            if field in data:
+                updates[f'profile.{field}'] = (data[field] or '').strip()
+        if 'favorite_genres' in data:
+            # De-duplicate while keeping the user's order
+            updates['profile.favorite_genres'] = list(dict.fromkeys(g.strip() for g in data['favorite_genres']))
+        if 'visibility' in data:
+            updates['profile.visibility'] = data['visibility']
 
-        if not query:
-            print("[Friends] Empty query, returning empty array")
-            return jsonify([]), 200
+        if not updates:
+            return jsonify({'error': 'No profile fields to update'}), 400
 
-        # Get total user count for debugging
-        total_users = users_collection.count_documents({})
-        print(f"[Friends] Total users in database: {total_users}")
+        users_collection.update_one({'_id': ObjectId(user_id)}, {'$set': updates})
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
 
-        users = list(users_collection.find({
-            'username': {'$regex': query, '$options': 'i'},
-            '_id': {'$ne': ObjectId(user_id)}
-        }).limit(10))
+        return jsonify(_profile_response(user)), 200
 
-        print(f"[Friends] Found {len(users)} users matching '{query}'")
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-        results = [{
-            'id': str(user['_id']),
-            'username': user['username']
-        } for user in users]
 
-        print(f"[Friends] Returning results: {[u['username'] for u in results]}")
+def _remove_avatar_file(filename):
+    if filename:
+        try:
+            os.remove(os.path.join(AVATAR_DIR, os.path.basename(filename)))
+        except OSError:
+            pass
 
-        return jsonify(results), 200
 
+@app.route('/api/profile/avatar', methods=['POST'])
+@jwt_required()
+def upload_avatar():
+    """Multipart upload (field 'avatar'); the image is center-cropped to a square and stored as PNG"""
+    try:
+        user_id = get_jwt_identity()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
+
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        too_large = f'Avatar must be under {AVATAR_MAX_BYTES // (1024 * 1024)} MB'
+        # Check the declared size before the body is parsed; the form fields around the file add a little
+        if (request.content_length or 0) > AVATAR_MAX_BYTES + 64 * 1024:
+            return jsonify({'error': too_large}), 413
+        upload = request.files.get('avatar')
+        if not upload:
+            return jsonify({'error': 'avatar file is required'}), 400
+
+        raw = upload.read(AVATAR_MAX_BYTES + 1)
+        if len(raw) > AVATAR_MAX_BYTES:
+            return jsonify({'error': too_large}), 413
+
+        not_an_image = 'Avatar must be a PNG, JPEG, GIF or WebP image'
+        try:
+            # open() only reads the header; it refuses images over Image.MAX_IMAGE_PIXELS
+            image = Image.open(BytesIO(raw))
+            if image.format not in AVATAR_FORMATS:
+                return jsonify({'error': not_an_image}), 400
+            image = ImageOps.exif_transpose(image).convert('RGBA')
+        except (Image.DecompressionBombError, Image.DecompressionBombWarning):
+            return jsonify({'error': 'Avatar dimensions are too large'}), 400
+        except (UnidentifiedImageError, OSError):
+            return jsonify({'error': not_an_image}), 400
+
+        image = ImageOps.fit(image, (AVATAR_SIZE, AVATAR_SIZE), Image.LANCZOS)
+        filename = f"{user_id}-{secrets.token_hex(6)}.png"
+        image.save(os.path.join(AVATAR_DIR, filename), 'PNG', optimize=True)
+
+        old_avatar = (user.get('profile') or {}).get('avatar')
+        users_collection.update_one({'_id': user['_id']}, {'$set': {'profile.avatar': filename}})
+        _remove_avatar_file(old_avatar)
+
+        return jsonify({'avatar_url': _avatar_url({'avatar': filename})}), 200
+
+    except RequestEntityTooLarge:
+        return jsonify({'error': f'Avatar must be under {AVATAR_MAX_BYTES // (1024 * 1024)} MB'}), 413
     except Exception as e:
-        print(f"[Friends] Error in search_users: {str(e)}")
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/friends/request', methods=['POST'])
+@app.route('/api/profile/avatar', methods=['DELETE'])
 @jwt_required()
-def send_friend_request():
+def delete_avatar():
     try:
         user_id = get_jwt_identity()
-        data = request.get_json()
-        to_username = data.get('username')
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
 
-        if not to_username:
-            return jsonify({'error': 'Username is required'}), 400
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
 
-        to_user = users_collection.find_one({'username': to_username})
+        _remove_avatar_file((user.get('profile') or {}).get('avatar'))
+        users_collection.update_one({'_id': user['_id']}, {'$unset': {'profile.avatar': ''}})
 
-        if not to_user:
-            return jsonify({'error': 'User not found'}), 404
+        return jsonify({'message': 'Avatar removed'}), 200
 
-        to_user_id = to_user['_id']
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-        # Check if already friends
-        user = users_collection.find_one({'_id': ObjectId(user_id)})
-        if to_user_id in user.get('friends', []):
-            return jsonify({'error': 'Already friends'}), 400
 
-        # Check if request already exists
-        existing_request = friend_requests_collection.find_one({
-            '$or': [
-                {'from_user_id': ObjectId(user_id), 'to_user_id': to_user_id, 'status': 'pending'},
-                {'from_user_id': to_user_id, 'to_user_id': ObjectId(user_id), 'status': 'pending'}
-            ]
-        })
+@app.route('/api/users/avatars/<filename>')
+def serve_avatar(filename):
+    return send_from_directory(AVATAR_DIR, filename)
 
-        if existing_request:
-            return jsonify({'error': 'Friend request already pending'}), 400
 
-        # Create friend request
-        friend_request = {
-            'from_user_id': ObjectId(user_id),
-            'to_user_id': to_user_id,
-            'status': 'pending',
-            'created_at': datetime.utcnow()
-        }
+@app.route('/api/users/<username>', methods=['GET'])
+@jwt_required(optional=True)
+def get_user_profile(username):
+    """Public profile lookup; what is returned depends on the owner's visibility setting"""
+    try:
+        user = users_collection.find_one({'username': username})
+        viewer_id = get_jwt_identity()
 
-        friend_requests_collection.insert_one(friend_request)
+        if not user or (viewer_id and ObjectId(viewer_id) in _id_list(user.get('blocked_users'))):
+            return jsonify({'error': 'User not found'}), 404
 
-        return jsonify({'message': 'Friend request sent'}), 201
+        can_see, is_self, is_friend = _profile_access(user, viewer_id)
+
+        result = _profile_response(user, full=can_see)
+        result['is_friend'] = is_friend
+        result['is_self'] = is_self
+        if can_see:
+            result['review_count'] = ratings_collection.count_documents({'user_id': user['_id'], 'review': {'$exists': True}})
+
+        return jsonify(result), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/friends/requests', methods=['GET'])
+@app.route('/api/users/<username>/reviews', methods=['GET'])
 @jwt_required()
-def get_friend_requests():
+def get_user_reviews(username):
+    """A user's reviews, newest first, if their profile is visible to you. ?limit=, ?offset="""
     try:
-        user_id = get_jwt_identity()
+        viewer_id = get_jwt_identity()
+        user = users_collection.find_one({'username': username})
+        if not user or ObjectId(viewer_id) in _id_list(user.get('blocked_users')):
+            return jsonify({'error': 'User not found'}), 404
 
-        # Get pending requests sent to this user
-        requests = list(friend_requests_collection.find({
-            'to_user_id': ObjectId(user_id),
-            'status': 'pending'
-        }))
+        can_see, _, _ = _profile_access(user, viewer_id)
+        if not can_see:
+            return jsonify({'error': 'This profile is private'}), 403
 
-        # Get sender info
-        result = []
-        for req in requests:
-            sender = users_collection.find_one({'_id': req['from_user_id']})
-            if sender:
-                result.append({
-                    'id': str(req['_id']),
-                    'from_user': {
-                        'id': str(sender['_id']),
-                        'username': sender['username']
-                    },
-                    'created_at': req['created_at'].isoformat()
+        offset, limit = _page_args()
+        reviews, next_offset = _review_page({'user_id': user['_id']}, ObjectId(viewer_id), offset, limit)
+        # Ratings from before titles were stored fall back to whatever is already cached
+        for review in reviews:
+            if not review['content']['title']:
+                cached = title_cache.get(f"{review['content_type']}:{review['content_id']}") or {}
+                review['content'] = {'title': cached.get('title'), 'poster_path': cached.get('poster_path')}
+        return jsonify({'reviews': reviews, 'next_offset': next_offset}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= WATCHLIST ROUTES =============
+
+# Lists are their own documents: the owner plus any collaborators (friends the owner invited)
+# can edit them, and privacy decides who else can read them. The older /api/watchlist
+# endpoints still work against the caller's own lists by name.
+
+DEFAULT_LIST_NAME = 'My Watchlist'
+LIST_ROLES = ('viewer', 'editor', 'owner')
+
+
+def _list_item(data, added_by, added_at=None):
+    return {
+        'item_id': ObjectId(),
+        'content_id': data['content_id'],
+        'content_type': data['content_type'],
+        'title': data.get('title'),
+        'poster_path': data.get('poster_path'),
+        'note': data.get('note') or '',
+        'added_by': added_by,
+        'added_at': added_at or datetime.utcnow()
+    }
+
+
+def _create_list(owner_id, name, description='', cover=None, privacy='friends'):
+    """Insert a new list; raises DuplicateKeyError if the owner already has one by that name"""
+    now = datetime.utcnow()
+    doc = {
+        'owner_id': owner_id,
+        'name': name,
+        'name_key': name.lower(),
+        'description': description or '',
+        'cover': cover,
+        'privacy': privacy,
+        'collaborators': [],
+        'items': [],
+        'share_token': None,
+        'created_at': now,
+        'updated_at': now
+    }
+    doc['_id'] = lists_collection.insert_one(doc).inserted_id
+    return doc
+
+
+def _owned_list(owner_id, name, create=False):
+    """
+    The owner's list with this name (case-insensitive), optionally creating it. Lists made
+    here weren't asked for (migrated watchlists, the default list, imports), so they start private.
+    """
+    lst = lists_collection.find_one({'owner_id': owner_id, 'name_key': name.lower()})
+    if lst or not create:
+        return lst
+    try:
+        return _create_list(owner_id, name, privacy='private')
+    except DuplicateKeyError:
+        return lists_collection.find_one({'owner_id': owner_id, 'name_key': name.lower()})
+
+
+def _migrate_user_watchlist(user_id):
+    """
+    Move a user's old embedded watchlist (items tagged with a list_name) into list documents.
+    Safe to run more than once; returns how many lists were touched.
+    """
+    user = users_collection.find_one({'_id': user_id, 'watchlist': {'$exists': True}}, {'watchlist': 1})
+    if not user:
+        return 0
+
+    by_name = {}
+    for item in user.get('watchlist') or []:
+        by_name.setdefault(item.get('list_name') or DEFAULT_LIST_NAME, []).append(item)
+
+    for name, items in by_name.items():
+        lst = _owned_list(user_id, name, create=True)
+        seen = {(str(i['content_id']), i['content_type']) for i in lst['items']}
+        new_items = []
+        for item in items:
+            key = (str(item['content_id']), item['content_type'])
+            if key not in seen:
+                seen.add(key)
+                new_items.append(_list_item(item, user_id, item.get('added_at')))
+        if new_items:
+            lists_collection.update_one({'_id': lst['_id']}, {'$push': {'items': {'$each': new_items}}})
+
+    users_collection.update_one({'_id': user_id}, {'$unset': {'watchlist': ''}})
+    return len(by_name)
+
+
+def _list_role(lst, user_id):
+    """owner, editor (collaborator), viewer (privacy allows it) or None"""
+    if user_id is None:
+        return 'viewer' if lst.get('privacy') == 'public' else None
+    user_id = ObjectId(user_id)
+    if lst['owner_id'] == user_id:
+        return 'owner'
+    if _is_blocked_between(lst['owner_id'], user_id):
+        return None
+    if user_id in lst.get('collaborators', []):
+        return 'editor'
+    if lst.get('privacy') == 'public' or (lst.get('privacy') == 'friends' and _are_friends(lst['owner_id'], user_id)):
+        return 'viewer'
+    return None
+
+
+def _load_list(list_id, user_id, need='viewer'):
+    """(list, role, None) or (None, None, error response). Lists you can't see are reported as missing."""
+    lst = lists_collection.find_one({'_id': ObjectId(list_id)}) if ObjectId.is_valid(list_id) else None
+    role = _list_role(lst, user_id) if lst else None
+    if not role:
+        return None, None, (jsonify({'error': 'List not found'}), 404)
+    if LIST_ROLES.index(role) < LIST_ROLES.index(need):
+        message = 'Only the list owner can do that' if need == 'owner' else 'You can only view this list'
+        return None, None, (jsonify({'error': message}), 403)
+    return lst, role, None
+
+
+def _list_usernames(lists):
+    ids = set()
+    for lst in lists:
+        ids.add(lst['owner_id'])
+        ids.update(lst.get('collaborators', []))
+        ids.update(i['added_by'] for i in lst.get('items', []) if i.get('added_by'))
+    return {u['_id']: u['username'] for u in users_collection.find({'_id': {'$in': list(ids)}}, {'username': 1})}
+
+
+def _list_response(lst, role, usernames, items=True):
+    result = {
+        'id': str(lst['_id']),
+        'name': lst['name'],
+        'description': lst.get('description', ''),
+        'cover': lst.get('cover'),
+        'privacy': lst.get('privacy', 'friends'),
+        'owner': {'id': str(lst['owner_id']), 'username': usernames.get(lst['owner_id'])},
+        'collaborators': [{'id': str(c), 'username': usernames.get(c)} for c in lst.get('collaborators', [])],
+        'item_count': len(lst.get('items', [])),
+        'role': role,
+        'shared': bool(lst.get('share_token')),
+        'created_at': lst['created_at'].isoformat(),
+        'updated_at': lst['updated_at'].isoformat()
+    }
+    if role == 'owner' and lst.get('share_token'):
+        result['share_url'] = f"{_app_base_url()}/?list={lst['share_token']}"
+    if items:
+        result['items'] = [{
+            'id': str(i['item_id']),
+            'content_id': i['content_id'],
+            'content_type': i['content_type'],
+            'title': i.get('title'),
+            'poster_path': i.get('poster_path'),
+            'note': i.get('note', ''),
+            'added_by': usernames.get(i.get('added_by')),
+            'added_at': i['added_at'].isoformat()
+        } for i in lst.get('items', [])]
+    return result
+
+
+def _list_changed(lst, action, actor_id):
+    """Let the owner and collaborators refresh a list someone else just edited"""
+    for member in [lst['owner_id'], *lst.get('collaborators', [])]:
+        if member != actor_id:
+            _push_to_user(str(member), 'list_changed', {'list_id': str(lst['_id']), 'action': action})
+
+
+def _end_collaboration(a, b):
+    """Take each of two users off the other's lists, e.g. when they unfriend or block"""
+    a, b = ObjectId(a), ObjectId(b)
+    for owner_id, collaborator_id in ((a, b), (b, a)):
+        lists_collection.update_many(
+            {'owner_id': owner_id, 'collaborators': collaborator_id},
+            {'$pull': {'collaborators': collaborator_id}}
+        )
+
+
+def _content_id_values(content_id):
+    """TMDB ids arrive as ints or strings - match either"""
+    try:
+        return [content_id, int(content_id)]
+    except ValueError:
+        return [content_id]
+
+
+@app.route('/api/lists', methods=['GET'])
+@jwt_required()
+def get_lists():
+    """The caller's own lists and the ones they collaborate on. ?items=1 includes the items."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_watchlist(user_id)
+
+        lists = list(lists_collection.find({'$or': [{'owner_id': user_id}, {'collaborators': user_id}]}).sort('created_at', 1))
+        usernames = _list_usernames(lists)
+        with_items = request.args.get('items') in ('1', 'true')
+        return jsonify([_list_response(lst, _list_role(lst, user_id), usernames, with_items) for lst in lists]), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists', methods=['POST'])
+@jwt_required()
+@validate_json(LIST_SCHEMA)
+def create_list():
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
+        _migrate_user_watchlist(user_id)
+
+        try:
+            lst = _create_list(user_id, data['name'].strip(), data.get('description'), data.get('cover'),
+                               data.get('privacy') or 'friends')
+        except DuplicateKeyError:
+            return jsonify({'error': 'A list with this name already exists'}), 400
+
+        return jsonify(_list_response(lst, 'owner', _list_usernames([lst]))), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>', methods=['GET'])
+@jwt_required(optional=True)
+def get_list(list_id):
+    try:
+        user_id = get_jwt_identity()
+        lst, role, error = _load_list(list_id, user_id)
+        if error:
+            return error
+        return jsonify(_list_response(lst, role, _list_usernames([lst]))), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>', methods=['PUT'])
+@jwt_required()
+@validate_json(LIST_UPDATE_SCHEMA)
+def update_list(list_id):
+    """Rename or change description, cover and privacy. Collaborators can edit everything but privacy."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
+        lst, role, error = _load_list(list_id, user_id, need='editor')
+        if error:
+            return error
+
+        updates = {}
+        if data.get('name'):
+            updates.update({'name': data['name'].strip(), 'name_key': data['name'].strip().lower()})
+        for field in ('description', 'cover'):
+            if field in data:
+                updates[field] = data[field] or ('' if field == 'description' else None)
+        if data.get('privacy'):
+            if role != 'owner':
+                return jsonify({'error': 'Only the list owner can change its privacy'}), 403
+            updates['privacy'] = data['privacy']
+        if not updates:
+            return jsonify({'error': 'Nothing to update'}), 400
+
+        updates['updated_at'] = datetime.utcnow()
+        try:
+            lst = lists_collection.find_one_and_update({'_id': lst['_id']}, {'$set': updates},
+                                                       return_document=ReturnDocument.AFTER)
+        except DuplicateKeyError:
+            return jsonify({'error': 'A list with this name already exists'}), 400
+
+        _list_changed(lst, 'updated', user_id)
+        return jsonify(_list_response(lst, role, _list_usernames([lst]))), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>', methods=['DELETE'])
+@jwt_required()
+def delete_list(list_id):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='owner')
+        if error:
+            return error
+
+        lists_collection.delete_one({'_id': lst['_id']})
+        _list_changed(lst, 'deleted', user_id)
+        return jsonify({'message': f'Deleted list "{lst["name"]}"'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/items', methods=['POST'])
+@jwt_required()
+@validate_json(LIST_ITEM_SCHEMA)
+def add_list_item(list_id):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
+        lst, _, error = _load_list(list_id, user_id, need='editor')
+        if error:
+            return error
+
+        item = _list_item(data, user_id)
+        result = lists_collection.update_one(
+            {'_id': lst['_id'], 'items': {'$not': {'$elemMatch': {
+                'content_id': {'$in': _content_id_values(str(data['content_id']))},
+                'content_type': data['content_type']
+            }}}},
+            {'$push': {'items': item}, '$set': {'updated_at': item['added_at']}}
+        )
+        if result.matched_count == 0:
+            return jsonify({'error': 'Item already in this list'}), 400
+
+        _list_changed(lst, 'item_added', user_id)
+        lst['items'].append(item)
+        return jsonify(_list_response(lst, None, _list_usernames([lst]))['items'][-1]), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/items/<item_id>', methods=['PUT'])
+@jwt_required()
+@validate_json(LIST_ITEM_NOTE_SCHEMA)
+def update_list_item(list_id, item_id):
+    """Set the note on an item"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='editor')
+        if error:
+            return error
+        if not ObjectId.is_valid(item_id):
+            return jsonify({'error': 'Item not found'}), 404
+
+        result = lists_collection.update_one(
+            {'_id': lst['_id'], 'items.item_id': ObjectId(item_id)},
+            {'$set': {'items.$.note': request.get_json().get('note') or '', 'updated_at': datetime.utcnow()}}
+        )
+        if result.matched_count == 0:
+            return jsonify({'error': 'Item not found'}), 404
+
+        _list_changed(lst, 'item_updated', user_id)
+        return jsonify({'message': 'Note saved'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/items/<item_id>', methods=['DELETE'])
+@jwt_required()
+def remove_list_item(list_id, item_id):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='editor')
+        if error:
+            return error
+        if not ObjectId.is_valid(item_id):
+            return jsonify({'error': 'Item not found'}), 404
+
+        result = lists_collection.update_one(
+            {'_id': lst['_id'], 'items.item_id': ObjectId(item_id)},
+            {'$pull': {'items': {'item_id': ObjectId(item_id)}}, '$set': {'updated_at': datetime.utcnow()}}
+        )
+        if result.matched_count == 0:
+            return jsonify({'error': 'Item not found'}), 404
+
+        _list_changed(lst, 'item_removed', user_id)
+        return jsonify({'message': 'Removed from list'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/order', methods=['PUT'])
+@jwt_required()
+@validate_json(LIST_ORDER_SCHEMA)
+def reorder_list(list_id):
+    """Save a manual order. item_ids must name every item exactly once."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='editor')
+        if error:
+            return error
+
+        order = [ObjectId(i) for i in request.get_json()['item_ids']]
+        items = {i['item_id']: i for i in lst['items']}
+        if len(order) != len(items) or set(order) != set(items):
+            return jsonify({'error': 'item_ids must list every item in the list exactly once'}), 400
+
+        # Only write if nobody changed the list since we read it
+        result = lists_collection.update_one(
+            {'_id': lst['_id'], 'updated_at': lst['updated_at']},
+            {'$set': {'items': [items[i] for i in order], 'updated_at': datetime.utcnow()}}
+        )
+        if result.matched_count == 0:
+            return jsonify({'error': 'The list changed while you were reordering it, reload and try again'}), 409
+
+        _list_changed(lst, 'reordered', user_id)
+        return jsonify({'message': 'Order saved'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/share', methods=['POST'])
+@jwt_required()
+def share_list(list_id):
+    """Create (or return) a link anyone can open to view the list, whatever its privacy"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='owner')
+        if error:
+            return error
+
+        if not lst.get('share_token'):
+            lst['share_token'] = secrets.token_urlsafe(16)
+            lists_collection.update_one({'_id': lst['_id']}, {'$set': {'share_token': lst['share_token']}})
+        return jsonify({'share_url': f"{_app_base_url()}/?list={lst['share_token']}"}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/share', methods=['DELETE'])
+@jwt_required()
+def unshare_list(list_id):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='owner')
+        if error:
+            return error
+
+        lists_collection.update_one({'_id': lst['_id']}, {'$set': {'share_token': None}})
+        return jsonify({'message': 'Share link disabled'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/shared/<token>', methods=['GET'])
+@jwt_required(optional=True)
+def get_shared_list(token):
+    try:
+        lst = lists_collection.find_one({'share_token': token}) if token else None
+        user_id = get_jwt_identity()
+        if not lst or (user_id and _is_blocked_between(lst['owner_id'], user_id)):
+            return jsonify({'error': 'List not found'}), 404
+
+        role = (_list_role(lst, user_id) if user_id else None) or 'viewer'
+        return jsonify(_list_response(lst, role, _list_usernames([lst]))), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/collaborators', methods=['POST'])
+@jwt_required()
+@validate_json(FRIEND_REQUEST_SCHEMA)
+def add_list_collaborator(list_id):
+    """Let a friend edit the list"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, _, error = _load_list(list_id, user_id, need='owner')
+        if error:
+            return error
+
+        friend = users_collection.find_one({'username': request.get_json()['username']}, {'username': 1})
+        if not friend or not _are_friends(user_id, friend['_id']):
+            return jsonify({'error': 'You can only add friends as collaborators'}), 400
+
+        result = lists_collection.update_one({'_id': lst['_id']}, {'$addToSet': {'collaborators': friend['_id']}})
+        if result.modified_count:
+            _notify(friend['_id'], 'list_collaborator', users_collection.find_one({'_id': user_id}),
+                    list_id=lst['_id'], list_name=lst['name'])
+        return jsonify({'message': f"{friend['username']} can now edit this list"}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/<list_id>/collaborators/<collaborator_id>', methods=['DELETE'])
+@jwt_required()
+def remove_list_collaborator(list_id, collaborator_id):
+    """The owner removes a collaborator, or a collaborator leaves"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        lst, role, error = _load_list(list_id, user_id, need='editor')
+        if error:
+            return error
+        if role != 'owner' and collaborator_id != str(user_id):
+            return jsonify({'error': 'Only the list owner can do that'}), 403
+        if not ObjectId.is_valid(collaborator_id):
+            return jsonify({'error': 'Not a collaborator'}), 404
+
+        result = lists_collection.update_one({'_id': lst['_id']}, {'$pull': {'collaborators': ObjectId(collaborator_id)}})
+        if result.modified_count == 0:
+            return jsonify({'error': 'Not a collaborator'}), 404
+        return jsonify({'message': 'Collaborator removed'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/users/<username>/lists', methods=['GET'])
+@jwt_required(optional=True)
+def get_user_lists(username):
+    """Lists a user owns that the caller is allowed to see"""
+    try:
+        viewer_id = get_jwt_identity()
+        user = users_collection.find_one({'username': username}, {'username': 1, 'blocked_users': 1})
+        if not user or (viewer_id and ObjectId(viewer_id) in _id_list(user.get('blocked_users'))):
+            return jsonify({'error': 'User not found'}), 404
+
+        lists = []
+        for lst in lists_collection.find({'owner_id': user['_id']}).sort('created_at', 1):
+            role = _list_role(lst, viewer_id)
+            if role:
+                lists.append((lst, role))
+        usernames = _list_usernames([lst for lst, _ in lists])
+        return jsonify([_list_response(lst, role, usernames, items=False) for lst, role in lists]), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/watchlist', methods=['GET'])
+@jwt_required()
+def get_watchlist():
+    """Every item in the caller's own lists, flattened and tagged with list_name"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_watchlist(user_id)
+
+        watchlist_items = []
+        for lst in lists_collection.find({'owner_id': user_id}).sort('created_at', 1):
+            for item in lst['items']:
+                watchlist_items.append({
+                    'content_id': item['content_id'],
+                    'content_type': item['content_type'],
+                    'title': item.get('title'),
+                    'poster_path': item.get('poster_path'),
+                    'list_name': lst['name'],
+                    'list_id': str(lst['_id']),
+                    'item_id': str(item['item_id']),
+                    'note': item.get('note', ''),
+                    'added_at': item['added_at']
                 })
 
-        return jsonify(result), 200
+        return jsonify(watchlist_items), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/watchlist', methods=['POST'])
+@jwt_required()
+@validate_json(WATCHLIST_ITEM_SCHEMA)
+def add_to_watchlist():
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
+        _migrate_user_watchlist(user_id)
+
+        # Get list name (default to "My Watchlist" for backwards compatibility)
+        list_name = data.get('list_name', DEFAULT_LIST_NAME)
+        lst = _owned_list(user_id, list_name, create=True)
+
+        item = _list_item(data, user_id)
+        result = lists_collection.update_one(
+            {'_id': lst['_id'], 'items': {'$not': {'$elemMatch': {
+                'content_id': {'$in': _content_id_values(str(data['content_id']))},
+                'content_type': data['content_type']
+            }}}},
+            {'$push': {'items': item}, '$set': {'updated_at': item['added_at']}}
+        )
+        if result.matched_count == 0:
+            return jsonify({'error': 'Item already in watchlist'}), 400
+
+        _list_changed(lst, 'item_added', user_id)
+        return jsonify({
+            'content_id': item['content_id'],
+            'content_type': item['content_type'],
+            'title': item['title'],
+            'poster_path': item['poster_path'],
+            'list_name': lst['name'],
+            'list_id': str(lst['_id']),
+            'item_id': str(item['item_id']),
+            'added_at': item['added_at']
+        }), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/watchlist/<content_id>', methods=['DELETE'])
+@jwt_required()
+def remove_from_watchlist(content_id):
+    """Remove a title from all of the caller's own lists"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_watchlist(user_id)
+
+        lists_collection.update_many(
+            {'owner_id': user_id, 'items.content_id': {'$in': _content_id_values(content_id)}},
+            {
+                '$pull': {'items': {'content_id': {'$in': _content_id_values(content_id)}}},
+                '$set': {'updated_at': datetime.utcnow()}
+            }
+        )
+
+        # Don't fail if item wasn't found - it might already be deleted
+        return jsonify({'message': 'Removed from watchlist'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/watchlist/rename', methods=['PUT'])
+@jwt_required()
+@validate_json(WATCHLIST_RENAME_SCHEMA)
+def rename_watchlist():
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
+        _migrate_user_watchlist(user_id)
+
+        old_name = data['old_name']
+        new_name = data['new_name'].strip()
+
+        lst = _owned_list(user_id, old_name)
+        if not lst:
+            return jsonify({'error': 'List not found'}), 404
+
+        try:
+            lists_collection.update_one(
+                {'_id': lst['_id']},
+                {'$set': {'name': new_name, 'name_key': new_name.lower(), 'updated_at': datetime.utcnow()}}
+            )
+        except DuplicateKeyError:
+            return jsonify({'error': 'A list with this name already exists'}), 400
+
+        return jsonify({'message': f'Renamed list from "{old_name}" to "{new_name}"'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= LIST IMPORT/EXPORT =============
+
+# Imports accept IMDb list CSVs, Letterboxd export ZIPs, Trakt JSON backups (a single file or a ZIP)
+# and our own JSON. Rows are resolved to TMDB titles; anything we can't pin down to exactly one
+# title is reported back instead of being guessed.
+
+LIST_FORMATS = ('json', 'imdb', 'letterboxd', 'trakt')
+LIST_IMPORT_MAX_BYTES = 10 * 1024 * 1024
+LIST_IMPORT_MAX_FILES = 500
+LIST_IMPORT_MAX_ITEMS = 500
+# Imports needing more TMDB lookups than this run in the background; the report is fetched afterwards
+LIST_IMPORT_SYNC_LOOKUPS = 25
+LIST_IMPORT_STALE_SECONDS = 3600
+LIST_EXPORT_FORMAT = 'glitchbox-lists'
+
+IMDB_TITLE_TYPES = {
+    'movie': 'movie', 'tvMovie': 'movie', 'short': 'movie', 'tvShort': 'movie', 'video': 'movie', 'tvSpecial': 'movie',
+    'tvSeries': 'tv', 'tvMiniSeries': 'tv'
+}
+IMDB_CSV_COLUMNS = ['Position', 'Const', 'Created', 'Modified', 'Description', 'Title', 'URL', 'Title Type', 'Year']
+
+
+def _import_name(filename):
+    """List name for a file that doesn't carry one: watchlist files go to the default list"""
+    stem = os.path.splitext(os.path.basename(filename or ''))[0]
+    if not stem or 'watchlist' in stem.lower():
+        return DEFAULT_LIST_NAME
+    if stem.lower().startswith('lists-'):
+        stem = stem[len('lists-'):]
+    return stem.replace('-', ' ').replace('_', ' ').strip()[:50] or DEFAULT_LIST_NAME
+
+
+def _safe_filename(name):
+    return re.sub(r'[^A-Za-z0-9._-]+', '-', name).strip('-') or 'list'
+
+
+def _parse_year(value):
+    value = str(value or '').strip()[:4]
+    return int(value) if value.isdigit() else None
+
+
+def _read_csv(raw):
+    return list(csv.DictReader(StringIO(raw.decode('utf-8-sig'))))
+
+
+def _parse_imdb(files):
+    lists = []
+    for filename, raw in files:
+        if not filename.lower().endswith('.csv'):
+            continue
+        rows = _read_csv(raw)
+        if rows and 'Const' not in rows[0] and 'Title' not in rows[0]:
+            raise ValueError(f'{filename} is not an IMDb list export')
+        entries = []
+        for row in rows:
+            title_type = (row.get('Title Type') or '').strip()
+            entries.append({
+                'title': (row.get('Title') or '').strip(),
+                'year': _parse_year(row.get('Year')),
+                'content_type': IMDB_TITLE_TYPES.get(title_type),
+                'imdb_id': (row.get('Const') or '').strip() or None,
+                'note': (row.get('Description') or '').strip(),
+                'skip': f'{title_type} titles are not supported' if title_type and title_type not in IMDB_TITLE_TYPES else None
+            })
+        lists.append({'name': _import_name(filename), 'entries': entries})
+    return lists
+
+
+def _parse_letterboxd_list(raw):
+    """A Letterboxd list CSV: a header block with the list's name and description, then the films"""
+    lines = raw.decode('utf-8-sig').splitlines()
+    start = next((i for i, line in enumerate(lines) if line.startswith('Position,')), None)
+    if start is None:
+        raise ValueError('Not a Letterboxd list export')
+    meta = list(csv.DictReader(lines[1:start]))
+    films = csv.DictReader(lines[start:])
+    return (meta[0] if meta else {}), films
+
+
+def _letterboxd_tmdb_id(url):
+    """Our own Letterboxd exports link films as letterboxd.com/tmdb/<id>/"""
+    found = re.search(r'letterboxd\.com/tmdb/(\d+)', url or '')
+    return int(found.group(1)) if found else None
+
+
+def _parse_letterboxd(files):
+    lists = []
+    for filename, raw in files:
+        name = filename.lower()
+        if name.startswith('deleted/') or not name.endswith('.csv'):
+            continue
+        if os.path.basename(name) == 'watchlist.csv':
+            meta, films = {'Name': DEFAULT_LIST_NAME}, _read_csv(raw)
+        elif name.startswith('lists/'):
+            meta, films = _parse_letterboxd_list(raw)
+        else:
+            continue
+        lists.append({
+            'name': (meta.get('Name') or _import_name(filename)).strip()[:50],
+            'description': (meta.get('Description') or '').strip(),
+            # Letterboxd only has films
+            'entries': [{
+                'title': (film.get('Name') or '').strip(),
+                'year': _parse_year(film.get('Year')),
+                'content_type': 'movie',
+                'tmdb_id': _letterboxd_tmdb_id(film.get('URL') or film.get('Letterboxd URI')),
+                'note': (film.get('Description') or '').strip()
+            } for film in films]
+        })
+    if not lists:
+        raise ValueError('No watchlist.csv or lists/ found - upload the ZIP from Letterboxd\'s data export')
+    return lists
+
+
+def _parse_trakt(files):
+    lists = []
+    for filename, raw in files:
+        base = os.path.basename(filename.lower())
+        if not base.endswith('.json') or (len(files) > 1 and 'watchlist' not in base and not base.startswith('lists')):
+            continue
+        data = json.loads(raw.decode('utf-8-sig'))
+        name = _import_name(filename)
+        if isinstance(data, dict):
+            name = (data.get('name') or name)[:50]
+            data = data.get('items') or []
+        if not isinstance(data, list):
+            raise ValueError(f'{filename} is not a Trakt list backup')
+        entries = []
+        for item in data:
+            kind = item.get('type') if isinstance(item, dict) else None
+            if kind not in ('movie', 'show'):
+                if kind:
+                    entries.append({'title': '', 'skip': f'{kind} entries are not supported'})
+                continue
+            title = item.get(kind) or {}
+            ids = title.get('ids') or {}
+            entries.append({
+                'title': title.get('title') or '',
+                'year': _parse_year(title.get('year')),
+                'content_type': 'tv' if kind == 'show' else 'movie',
+                'imdb_id': ids.get('imdb'),
+                'tmdb_id': ids.get('tmdb'),
+                'note': item.get('notes') or ''
+            })
+        if entries:
+            lists.append({'name': name, 'entries': entries})
+    return lists
+
+
+def _glitchbox_entry(item):
+    """Our own export rows are taken as-is, so they have to pass the same checks as adding an item"""
+    row = {k: item.get(k) for k in ('content_id', 'content_type', 'title', 'poster_path', 'note')}
+    errors = _schema_errors(LIST_ITEM_SCHEMA, row)
+    if errors:
+        return {'title': row['title'][:300] if isinstance(row['title'], str) else '',
+                'skip': 'Invalid entry: ' + ', '.join(f'{field} {message}' for field, message in errors)}
+    return {
+        'title': row['title'],
+        'content_type': row['content_type'],
+        # Our own ids are already TMDB ids
+        'tmdb_id': row['content_id'],
+        'poster_path': row['poster_path'],
+        'note': row['note'] or '',
+        'trusted': True
+    }
+
+
+def _parse_glitchbox(files):
+    lists = []
+    for filename, raw in files:
+        if not filename.lower().endswith('.json'):
+            continue
+        data = json.loads(raw.decode('utf-8-sig'))
+        if not isinstance(data, dict) or data.get('format') != LIST_EXPORT_FORMAT:
+            raise ValueError(f'{filename} is not a GlitchBox list export')
+        for lst in data.get('lists') or []:
+            if not isinstance(lst, dict):
+                continue
+            lists.append({
+                'name': str(lst.get('name') or DEFAULT_LIST_NAME)[:50],
+                'description': lst.get('description')[:500] if isinstance(lst.get('description'), str) else '',
+                'privacy': lst.get('privacy') if lst.get('privacy') in ('private', 'friends', 'public') else None,
+                'entries': [_glitchbox_entry(item) for item in lst.get('items') or [] if isinstance(item, dict)]
+            })
+    return lists
+
+
+LIST_PARSERS = {'json': _parse_glitchbox, 'imdb': _parse_imdb, 'letterboxd': _parse_letterboxd, 'trakt': _parse_trakt}
+
+
+def _upload_files(upload):
+    """[(filename, bytes)] for an uploaded file, unpacking ZIPs"""
+    raw = upload.read(LIST_IMPORT_MAX_BYTES + 1)
+    if len(raw) > LIST_IMPORT_MAX_BYTES:
+        raise ValueError(f'Import files must be under {LIST_IMPORT_MAX_BYTES // (1024 * 1024)} MB')
+    if not zipfile.is_zipfile(BytesIO(raw)):
+        return [(upload.filename or 'upload', raw)]
+
+    with zipfile.ZipFile(BytesIO(raw)) as archive:
+        infos = archive.infolist()
+        if len(infos) > LIST_IMPORT_MAX_FILES:
+            raise ValueError(f'ZIPs with more than {LIST_IMPORT_MAX_FILES} files can\'t be imported')
+        wanted = [info for info in infos
+                  if not info.is_dir() and info.filename.lower().endswith(('.csv', '.json'))]
+        # Check the unpacked size up front - reads never go past the sizes the archive declares
+        if sum(info.file_size for info in wanted) > LIST_IMPORT_MAX_BYTES:
+            raise ValueError(f'Import files must be under {LIST_IMPORT_MAX_BYTES // (1024 * 1024)} MB unzipped')
+        return [(info.filename, archive.read(info)) for info in wanted]
+
+
+def _tmdb_candidate(result, content_type):
+    date = result.get('release_date' if content_type == 'movie' else 'first_air_date') or ''
+    return {
+        'content_id': result['id'],
+        'content_type': content_type,
+        'title': result.get('title') if content_type == 'movie' else result.get('name'),
+        'year': _parse_year(date),
+        'poster_path': result.get('poster_path')
+    }
+
+
+def _normalize_title(title):
+    return re.sub(r'[^a-z0-9]+', ' ', (title or '').lower()).strip()
+
+
+def _resolve_import_entry(entry):
+    """
+    Match an imported row to a TMDB title by TMDB id, then IMDb id, then title and year.
+    Returns ('matched', candidate), ('ambiguous', candidates) or ('failed', reason).
+    """
+    content_type = entry.get('content_type')
+    if entry.get('tmdb_id') and content_type:
+        if entry.get('trusted') and entry.get('title'):
+            return 'matched', {'content_id': entry['tmdb_id'], 'content_type': content_type, 'title': entry['title'],
+                               'year': None, 'poster_path': entry.get('poster_path')}
+        details = tmdb.get_tv_details(entry['tmdb_id']) if content_type == 'tv' else tmdb.get_movie_details(entry['tmdb_id'])
+        if not details.get('error'):
+            return 'matched', _tmdb_candidate(details, content_type)
+
+    if entry.get('imdb_id'):
+        found = tmdb.find_by_imdb_id(entry['imdb_id'])
+        hits = ([_tmdb_candidate(r, 'movie') for r in found.get('movie_results', [])] +
+                [_tmdb_candidate(r, 'tv') for r in found.get('tv_results', [])])
+        if content_type:
+            hits = [h for h in hits if h['content_type'] == content_type] or hits
+        if len(hits) == 1:
+            return 'matched', hits[0]
+        if hits:
+            return 'ambiguous', hits
+
+    if not entry.get('title'):
+        return 'failed', 'No title or id to look up'
+
+    results = []
+    for kind in [content_type] if content_type else ['movie', 'tv']:
+        search = tmdb.search_movies if kind == 'movie' else tmdb.search_tv_shows
+        data = search(entry['title'], entry.get('year'))
+        if data.get('error'):
+            return 'failed', 'Title lookup failed, try again later'
+        results.extend(_tmdb_candidate(r, kind) for r in data.get('results', [])[:5])
+    if not results:
+        return 'failed', 'No match found'
+
+    wanted = _normalize_title(entry['title'])
+    exact = [r for r in results if _normalize_title(r['title']) == wanted and (not entry.get('year') or r['year'] == entry['year'])]
+    if len(exact) == 1:
+        return 'matched', exact[0]
+    return 'ambiguous', (exact or results)[:5]
+
+
+def _run_list_import(user_id, lists, dry_run):
+    """Resolve and save parsed lists; returns the import report"""
+    if not dry_run:
+        _migrate_user_watchlist(user_id)
+
+    report = {'matched': [], 'ambiguous': [], 'failed': [], 'lists': []}
+    resolved = {}
+    for imported in lists:
+        items = []
+        for entry in imported['entries']:
+            row = {'list_name': imported['name'], 'title': entry.get('title'), 'year': entry.get('year')}
+            if entry.get('skip'):
+                report['failed'].append({**row, 'reason': entry['skip']})
+                continue
+            key = tuple(entry.get(k) for k in ('tmdb_id', 'imdb_id', 'title', 'year', 'content_type'))
+            if key not in resolved:
+                resolved[key] = _resolve_import_entry(entry)
+            status, result = resolved[key]
+            if status == 'matched':
+                report['matched'].append({**row, 'match': result})
+                items.append({**result, 'note': entry.get('note')})
+            elif status == 'ambiguous':
+                report['ambiguous'].append({**row, 'candidates': result})
+            else:
+                report['failed'].append({**row, 'reason': result})
+
+        if dry_run or not items:
+            continue
+        existing = _owned_list(user_id, imported['name'])
+        lst = existing or _owned_list(user_id, imported['name'], create=True)
+        # Carry over the description, and the privacy of lists we're creating now
+        meta = {k: imported[k] for k in ('description', 'privacy')
+                if imported.get(k) and not (existing and (k == 'privacy' or lst.get(k)))}
+        seen = {(str(i['content_id']), i['content_type']) for i in lst['items']}
+        new_items = []
+        for item in items:
+            if (str(item['content_id']), item['content_type']) not in seen:
+                seen.add((str(item['content_id']), item['content_type']))
+                new_items.append(_list_item(item, user_id))
+        if new_items or meta:
+            lists_collection.update_one({'_id': lst['_id']}, {
+                '$push': {'items': {'$each': new_items}},
+                '$set': {**meta, 'updated_at': datetime.utcnow()}
+            })
+            _list_changed(lst, 'imported', user_id)
+        report['lists'].append({
+            'id': str(lst['_id']),
+            'name': lst['name'],
+            'added': len(new_items),
+            'already_in_list': len(items) - len(new_items)
+        })
+
+    report['summary'] = {k: len(report[k]) for k in ('matched', 'ambiguous', 'failed')}
+    report['dry_run'] = dry_run
+    return report
+
+
+def _list_import_job(import_id, user_id, lists, dry_run):
+    """Background side of a large import: stores the report (or the error) on the import document"""
+    try:
+        update = {'status': 'done', 'report': _run_list_import(user_id, lists, dry_run)}
+    except Exception as e:
+        print(f"[Lists] Import {import_id} failed: {e}")
+        update = {'status': 'failed', 'error': 'Import failed, try again later'}
+    list_imports_collection.update_one({'_id': import_id}, {'$set': {**update, 'finished_at': datetime.utcnow()}})
+    _push_to_user(str(user_id), 'list_import_finished', {'import_id': str(import_id), 'status': update['status']})
+
+
+def _list_import_response(job):
+    result = {'id': str(job['_id']), 'status': job['status'], 'created_at': job['created_at'].isoformat()}
+    if job['status'] == 'done':
+        result['report'] = job['report']
+    elif job['status'] == 'failed':
+        result['error'] = job.get('error')
+    return result
+
+
+@app.route('/api/lists/import', methods=['POST'])
+@jwt_required()
+def import_lists():
+    """
+    Multipart upload: 'file', 'format' (json, imdb, letterboxd or trakt) and optionally 'list_name'
+    to put everything into one list. ?dry_run=1 resolves titles without saving anything.
+    Returns what matched, what was ambiguous (with candidates to pick from) and what failed.
+    Imports needing many title lookups answer 202 with an import id instead; the report is then
+    fetched from GET /api/lists/import/<import_id> (a list_import_finished event says when).
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        # Refuse by the declared size before the form is parsed; MAX_CONTENT_LENGTH stops the rest
+        if (request.content_length or 0) > LIST_IMPORT_MAX_BYTES + 64 * 1024:
+            return jsonify({'error': f'Import files must be under {LIST_IMPORT_MAX_BYTES // (1024 * 1024)} MB'}), 413
+        import_format = (request.form.get('format') or '').lower()
+        if import_format not in LIST_FORMATS:
+            return jsonify({'error': f"format must be one of {', '.join(LIST_FORMATS)}"}), 400
+        upload = request.files.get('file')
+        if not upload:
+            return jsonify({'error': 'file is required'}), 400
+
+        try:
+            lists = LIST_PARSERS[import_format](_upload_files(upload))
+        except (ValueError, UnicodeDecodeError, zipfile.BadZipFile, csv.Error) as e:
+            return jsonify({'error': str(e) or 'Could not read the file'}), 400
+
+        target = (request.form.get('list_name') or '').strip()[:50]
+        if target:
+            lists = [{'name': target, 'entries': [e for lst in lists for e in lst['entries']]}]
+        if sum(len(lst['entries']) for lst in lists) > LIST_IMPORT_MAX_ITEMS:
+            return jsonify({'error': f'Imports are limited to {LIST_IMPORT_MAX_ITEMS} titles at a time'}), 400
+
+        dry_run = request.args.get('dry_run') in ('1', 'true')
+        lookups = sum(1 for lst in lists for e in lst['entries'] if not e.get('skip') and not e.get('trusted'))
+        if lookups <= LIST_IMPORT_SYNC_LOOKUPS:
+            return jsonify(_run_list_import(user_id, lists, dry_run)), 200
+
+        # One background import per user at a time
+        if list_imports_collection.find_one({
+            'user_id': user_id,
+            'status': 'running',
+            'created_at': {'$gt': datetime.utcnow() - timedelta(seconds=LIST_IMPORT_STALE_SECONDS)}
+        }, {'_id': 1}):
+            return jsonify({'error': 'An import is already running - wait for it to finish'}), 429
+
+        job = {'user_id': user_id, 'status': 'running', 'dry_run': dry_run, 'created_at': datetime.utcnow()}
+        job['_id'] = list_imports_collection.insert_one(job).inserted_id
+        socketio.start_background_task(_list_import_job, job['_id'], user_id, lists, dry_run)
+        return jsonify(_list_import_response(job)), 202
+
+    except RequestEntityTooLarge:
+        return jsonify({'error': f'Import files must be under {LIST_IMPORT_MAX_BYTES // (1024 * 1024)} MB'}), 413
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/lists/import/<import_id>', methods=['GET'])
+@jwt_required()
+def get_list_import(import_id):
+    """Status of a background import, with its report once it's done"""
+    try:
+        job = list_imports_collection.find_one({
+            '_id': ObjectId(import_id),
+            'user_id': ObjectId(get_jwt_identity())
+        }) if ObjectId.is_valid(import_id) else None
+        if not job:
+            return jsonify({'error': 'Import not found'}), 404
+        return jsonify(_list_import_response(job)), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# Exports needing more TMDB lookups than this look them up in the background first
+LIST_EXPORT_SYNC_LOOKUPS = LIST_IMPORT_SYNC_LOOKUPS
+LIST_EXPORT_RETRY_SECONDS = 15
+export_lookups_running = set()  # user ids with a background lookup under way
+
+
+def _export_lookups_needed(lists):
+    """Distinct titles on these lists whose year and IMDb id aren't cached yet"""
+    return list({(item['content_type'], item['content_id']) for lst in lists for item in lst['items']
+                 if not external_ids_cached(item['content_type'], item['content_id'])})
+
+
+def _export_lookup_job(user_id, titles):
+    try:
+        for content_type, content_id in titles:
+            get_content_external_ids(content_type, content_id)
+    finally:
+        export_lookups_running.discard(user_id)
+    _push_to_user(str(user_id), 'list_export_ready', {})
+
+
+def _export_rows(lst):
+    """A list's items with the year and IMDb id the other services key on"""
+    rows = []
+    for item in lst['items']:
+        rows.append({**item, **get_content_external_ids(item['content_type'], item['content_id'])})
+    return rows
+
+
+def _imdb_csv(lst):
+    out = StringIO()
+    writer = csv.DictWriter(out, fieldnames=IMDB_CSV_COLUMNS)
+    writer.writeheader()
+    for position, row in enumerate(_export_rows(lst), 1):
+        writer.writerow({
+            'Position': position,
+            'Const': row['imdb_id'] or '',
+            'Created': row['added_at'].strftime('%Y-%m-%d'),
+            'Modified': row['added_at'].strftime('%Y-%m-%d'),
+            'Description': row.get('note') or '',
+            'Title': row.get('title') or '',
+            'URL': f"https://www.imdb.com/title/{row['imdb_id']}/" if row['imdb_id'] else '',
+            'Title Type': 'tvSeries' if row['content_type'] == 'tv' else 'movie',
+            'Year': row['year'] or ''
+        })
+    return out.getvalue()
+
+
+def _letterboxd_csv(lst, watchlist=False):
+    """watchlist.csv or a lists/ file as Letterboxd exports them. Letterboxd has no TV, so shows are left out."""
+    out = StringIO()
+    writer = csv.writer(out)
+    films = [r for r in _export_rows(lst) if r['content_type'] == 'movie']
+    if watchlist:
+        writer.writerow(['Date', 'Name', 'Year', 'Letterboxd URI'])
+        for row in films:
+            writer.writerow([row['added_at'].strftime('%Y-%m-%d'), row.get('title') or '', row['year'] or '',
+                             f"https://letterboxd.com/tmdb/{row['content_id']}/"])
+        return out.getvalue()
+
+    writer.writerow(['Letterboxd list export v7'])
+    writer.writerow(['Date', 'Name', 'Tags', 'URL', 'Description'])
+    writer.writerow([lst['created_at'].strftime('%Y-%m-%d'), lst['name'], '', '', lst.get('description') or ''])
+    writer.writerow([])
+    writer.writerow(['Position', 'Name', 'Year', 'URL', 'Description'])
+    for position, row in enumerate(films, 1):
+        writer.writerow([position, row.get('title') or '', row['year'] or '',
+                         f"https://letterboxd.com/tmdb/{row['content_id']}/", row.get('note') or ''])
+    return out.getvalue()
+
+
+def _trakt_json(lst):
+    items = []
+    for row in _export_rows(lst):
+        kind = 'show' if row['content_type'] == 'tv' else 'movie'
+        items.append({
+            'listed_at': row['added_at'].strftime('%Y-%m-%dT%H:%M:%S.000Z'),
+            'type': kind,
+            'notes': row.get('note') or None,
+            kind: {
+                'title': row.get('title'),
+                'year': row['year'],
+                'ids': {'tmdb': int(row['content_id']) if str(row['content_id']).isdigit() else row['content_id'], 'imdb': row['imdb_id']}
+            }
+        })
+    return json.dumps(items, indent=2)
+
+
+def _glitchbox_json(lists):
+    return json.dumps(_json_safe({
+        'format': LIST_EXPORT_FORMAT,
+        'version': 1,
+        'exported_at': datetime.utcnow(),
+        'lists': [{
+            'name': lst['name'],
+            'description': lst.get('description', ''),
+            'cover': lst.get('cover'),
+            'privacy': lst.get('privacy', 'friends'),
+            'items': [{k: i.get(k) for k in ('content_id', 'content_type', 'title', 'poster_path', 'note', 'added_at')}
+                      for i in lst['items']]
+        } for lst in lists]
+    }), indent=2)
+
+
+@app.route('/api/lists/export', methods=['GET'])
+@jwt_required()
+def export_lists():
+    """
+    Download lists as ?format=json (default), imdb, letterboxd or trakt. ?list_id= exports one list,
+    otherwise all of your own and collaborative lists. Letterboxd is always a ZIP like its own export;
+    IMDb and Trakt are a single file for one list and a ZIP for several. Those three need each title's
+    year and IMDb id: when many aren't cached yet the answer is 202 while they're looked up in the
+    background - ask again after Retry-After (a list_export_ready event says when).
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        export_format = request.args.get('format', 'json').lower()
+        if export_format not in LIST_FORMATS:
+            return jsonify({'error': f"format must be one of {', '.join(LIST_FORMATS)}"}), 400
+
+        if request.args.get('list_id'):
+            lst, _, error = _load_list(request.args['list_id'], user_id)
+            if error:
+                return error
+            lists = [lst]
+        else:
+            _migrate_user_watchlist(user_id)
+            lists = list(lists_collection.find({'$or': [{'owner_id': user_id}, {'collaborators': user_id}]}).sort('created_at', 1))
+
+        if export_format != 'json':
+            needed = _export_lookups_needed(lists)
+            if len(needed) > LIST_EXPORT_SYNC_LOOKUPS:
+                if user_id not in export_lookups_running:
+                    export_lookups_running.add(user_id)
+                    socketio.start_background_task(_export_lookup_job, user_id, needed)
+                response = jsonify({'status': 'preparing', 'pending_titles': len(needed)})
+                response.headers['Retry-After'] = str(LIST_EXPORT_RETRY_SECONDS)
+                return response, 202
+
+        filename = f"glitchbox-lists-{datetime.utcnow().strftime('%Y%m%d')}"
+        if len(lists) == 1:
+            filename = f"{_safe_filename(lists[0]['name'])}-{datetime.utcnow().strftime('%Y%m%d')}"
+
+        if export_format == 'json':
+            response = Response(_glitchbox_json(lists), mimetype='application/json')
+            response.headers['Content-Disposition'] = f'attachment; filename="{filename}.json"'
+            return response
+
+        if export_format == 'letterboxd':
+            files = {}
+            for lst in lists:
+                if lst['name'] == DEFAULT_LIST_NAME and 'watchlist.csv' not in files:
+                    files['watchlist.csv'] = _letterboxd_csv(lst, watchlist=True)
+                else:
+                    files[f"lists/{_safe_filename(lst['name'])}.csv"] = _letterboxd_csv(lst)
+        elif export_format == 'imdb':
+            files = {f"{_safe_filename(lst['name'])}.csv": _imdb_csv(lst) for lst in lists}
+        else:
+            files = {('watchlist.json' if lst['name'] == DEFAULT_LIST_NAME else f"lists-{_safe_filename(lst['name'])}.json"): _trakt_json(lst)
+                     for lst in lists}
+
+        if len(files) == 1 and export_format != 'letterboxd':
+            (name, content), = files.items()
+            mimetype = 'text/csv' if name.endswith('.csv') else 'application/json'
+            response = Response(content, mimetype=mimetype)
+            response.headers['Content-Disposition'] = f'attachment; filename="{filename}{os.path.splitext(name)[1]}"'
+            return response
+
+        buffer = BytesIO()
+        with zipfile.ZipFile(buffer, 'w', zipfile.ZIP_DEFLATED) as archive:
+            for name, content in files.items():
+                archive.writestr(name, content)
+        buffer.seek(0)
+        return send_file(buffer, mimetype='application/zip', as_attachment=True,
+                         download_name=f"{filename}-{export_format}.zip")
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= WATCH HISTORY ROUTES =============
+
+# One record per movie or episode a user has played: how far they got, how long it runs, whether
+# they finished it and when each viewing session happened. Continue watching, the activity feed's
+# "watching" items and viewing stats are all built from these records.
+
+WATCH_COMPLETE_RATIO = 0.9
+WATCH_SESSION_GAP = timedelta(minutes=30)
+WATCH_SESSIONS_KEPT = 50
+
+
+def _history_key(content_type, content_id, season=None, episode=None):
+    if content_type == 'tv' and season is not None and episode is not None:
+        return _episode_key(content_id, season, episode)
+    return f"{content_type}:{content_id}"
+
+
+def _record_progress(user_id, data, at=None, session=True):
+    """
+    Save a progress report for a movie or episode. A report more than WATCH_SESSION_GAP after the
+    previous one starts a new session. Reaching 90% (or sending completed) marks it finished, and it
+    stays finished until the record is removed. Without progress_seconds the position is left alone,
+    which is how players say "started playing" before they know where they are. Older clients only
+    know a percentage (progress_percent); it's kept until a real position replaces it.
+    """
+    at = at or datetime.utcnow()
+    content_type = data['content_type']
+    content_id = int(data['content_id']) if str(data['content_id']).isdigit() else data['content_id']
+    season, episode = (data.get('season'), data.get('episode')) if content_type == 'tv' else (None, None)
+    key = _history_key(content_type, content_id, season, episode)
+
+    progress = data.get('progress_seconds')
+    duration = data.get('duration_seconds')
+    percent = max(0, min(data['progress_percent'], 100)) if data.get('progress_percent') is not None else None
+    completed = (bool(data.get('completed')) or bool(duration and progress and progress >= duration * WATCH_COMPLETE_RATIO)
+                 or bool(percent and percent >= WATCH_COMPLETE_RATIO * 100))
+
+    existing = watch_history_collection.find_one(
+        {'user_id': user_id, 'content_key': key},
+        {'sessions': {'$slice': -1}, 'completed': 1}
+    )
+    fields = {
+        'content_id': content_id,
+        'content_type': content_type,
+        'season': season,
+        'episode': episode,
+        'last_watched_at': at,
+        'hidden': False
+    }
+    fields.update({k: data[k] for k in ('title', 'poster_path', 'progress_seconds', 'duration_seconds') if data.get(k) is not None})
+    update = {'$set': fields, '$setOnInsert': {'first_watched_at': at}}
+    if progress is None:
+        update['$setOnInsert']['progress_seconds'] = 0
+    else:
+        update['$unset'] = {'progress_percent': ''}
+    if percent is not None:
+        fields['progress_percent'] = percent
+    if completed and not (existing and existing.get('completed')):
+        fields.update({'completed': True, 'completed_at': at})
+    elif not existing:
+        update['$setOnInsert']['completed'] = False
+
+    array_filters = None
+    last_session = ((existing or {}).get('sessions') or [None])[-1]
+    if session and last_session and at - last_session['ended_at'] <= WATCH_SESSION_GAP:
+        fields['sessions.$[current].ended_at'] = at
+        array_filters = [{'current.started_at': last_session['started_at']}]
+    elif session:
+        update['$push'] = {'sessions': {'$each': [{'started_at': at, 'ended_at': at}], '$slice': -WATCH_SESSIONS_KEPT}}
+
+    return watch_history_collection.find_one_and_update(
+        {'user_id': user_id, 'content_key': key}, update, upsert=True,
+        array_filters=array_filters, return_document=ReturnDocument.AFTER
+    )
+
+
+def _history_response(record, sessions=False):
+    duration = record.get('duration_seconds')
+    percent = None
+    if duration:
+        percent = round(min(record.get('progress_seconds', 0) / duration, 1) * 100)
+    elif record.get('progress_percent') is not None:
+        percent = round(record['progress_percent'])
+    elif record.get('completed'):
+        percent = 100
+    result = {
+        'content_id': record['content_id'],
+        'content_type': record['content_type'],
+        'season': record.get('season'),
+        'episode': record.get('episode'),
+        'title': record.get('title'),
+        'poster_path': record.get('poster_path'),
+        'progress_seconds': record.get('progress_seconds', 0),
+        'duration_seconds': duration,
+        'percent': 100 if record.get('completed') else percent,
+        'completed': record.get('completed', False),
+        'completed_at': record['completed_at'].isoformat() if record.get('completed_at') else None,
+        'first_watched_at': record['first_watched_at'].isoformat(),
+        'last_watched_at': record['last_watched_at'].isoformat()
+    }
+    if sessions:
+        result['sessions'] = [{'started_at': s['started_at'].isoformat(), 'ended_at': s['ended_at'].isoformat()}
+                              for s in record.get('sessions', [])]
+    return result
+
+
+def _migrate_user_continue_watching(user_id):
+    """Turn a user's old continue_watching array into watch history records. Returns how many items moved."""
+    user = users_collection.find_one({'_id': user_id, 'continue_watching': {'$exists': True}}, {'continue_watching': 1})
+    if not user:
+        return 0
+    moved = 0
+    for item in user.get('continue_watching') or []:
+        if item.get('content_id') is None or item.get('content_type') not in ('movie', 'tv'):
+            continue
+        _record_progress(user_id, {
+            'content_id': item['content_id'],
+            'content_type': item['content_type'],
+            'season': item.get('season'),
+            'episode': item.get('episode'),
+            'title': item.get('title'),
+            'poster_path': item.get('poster_path'),
+            # The old progress was a percentage, so where they got to in seconds is unknown
+            'progress_percent': item.get('progress') if isinstance(item.get('progress'), (int, float)) else None
+        }, at=item.get('last_watched'))
+        moved += 1
+    users_collection.update_one({'_id': user_id}, {'$unset': {'continue_watching': ''}})
+    return moved
+
+
+def _continue_watching(user_id):
+    """The most recent record per title, leaving out finished movies. Shows stay listed on their latest episode."""
+    pipeline = [
+        {'$match': {'user_id': user_id, 'hidden': {'$ne': True}}},
+        {'$sort': {'last_watched_at': -1}},
+        {'$group': {'_id': {'type': '$content_type', 'id': '$content_id'}, 'latest': {'$first': '$$ROOT'}}},
+        {'$replaceRoot': {'newRoot': '$latest'}},
+        {'$match': {'$or': [{'content_type': 'tv'}, {'completed': {'$ne': True}}]}},
+        {'$sort': {'last_watched_at': -1}}
+    ]
+    return list(watch_history_collection.aggregate(pipeline))
+
+
+@app.route('/api/history', methods=['POST'])
+@jwt_required()
+@validate_json(WATCH_PROGRESS_SCHEMA, check=_watch_progress_checks)
+def record_watch_progress():
+    """Report playback position for a movie or episode. Players call this every so often while playing."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+        record = _record_progress(user_id, request.get_json())
+        return jsonify(_history_response(record)), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/history', methods=['GET'])
+@jwt_required()
+def get_watch_history():
+    """Newest first. ?content_type= and ?content_id= to narrow it down, ?limit=, ?offset="""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        query = {'user_id': user_id}
+        if request.args.get('content_type'):
+            query['content_type'] = request.args['content_type']
+        if request.args.get('content_id'):
+            query['content_id'] = {'$in': _content_id_values(request.args['content_id'])}
+
+        offset, limit = _page_args(default_limit=50, max_limit=200)
+        records = list(watch_history_collection.find(query).sort('last_watched_at', -1).skip(offset).limit(limit + 1))
+        next_offset = offset + limit if len(records) > limit else None
+        return jsonify({
+            'history': [_history_response(r, sessions=True) for r in records[:limit]],
+            'next_offset': next_offset
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/history', methods=['DELETE'])
+@jwt_required()
+def clear_watch_history():
+    """Forget everything watched, optionally just one type (?content_type=), title (&content_id=) or episode (&season=&episode=)"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        query = {'user_id': user_id}
+        if request.args.get('content_type'):
+            query['content_type'] = request.args['content_type']
+        if request.args.get('content_id'):
+            query['content_id'] = {'$in': _content_id_values(request.args['content_id'])}
+            for field in ('season', 'episode'):
+                if request.args.get(field) is not None:
+                    query[field] = request.args.get(field, type=int)
+
+        removed = watch_history_collection.delete_many(query).deleted_count
+        return jsonify({'message': 'Watch history cleared', 'removed': removed}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/history/tv/<int:tv_id>/season/<int:season_number>/watched', methods=['POST'])
+@jwt_required()
+def mark_season_watched(tv_id, season_number):
+    """Mark every episode of a season that has aired as watched. TMDB runtimes become the durations."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        season = tmdb.get_tv_season_details(tv_id, season_number, include_ratings=False)
+        if season.get('error'):
+            return jsonify({'error': 'Could not load this season from TMDB'}), 502
+
+        show = get_content_summary('tv', tv_id)
+        already = {r['episode'] for r in watch_history_collection.find(
+            {'user_id': user_id, 'content_type': 'tv', 'content_id': tv_id, 'season': season_number, 'completed': True},
+            {'episode': 1})}
+        today = datetime.utcnow().strftime('%Y-%m-%d')
+        marked = 0
+        for ep in season.get('episodes', []):
+            if ep['episode_number'] in already or not ep.get('air_date') or ep['air_date'] > today:
+                continue
+            runtime = (ep.get('runtime') or 0) * 60 or None
+            _record_progress(user_id, {
+                'content_id': tv_id,
+                'content_type': 'tv',
+                'season': season_number,
+                'episode': ep['episode_number'],
+                'title': show['title'],
+                'poster_path': show['poster_path'],
+                'progress_seconds': runtime or 0,
+                'duration_seconds': runtime,
+                'completed': True
+            }, session=False)
+            marked += 1
+
+        return jsonify({'message': f'Marked {marked} episodes watched', 'marked': marked}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/history/tv/<int:tv_id>/season/<int:season_number>/watched', methods=['DELETE'])
+@jwt_required()
+def unmark_season_watched(tv_id, season_number):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        removed = watch_history_collection.delete_many(
+            {'user_id': user_id, 'content_type': 'tv', 'content_id': tv_id, 'season': season_number}
+        ).deleted_count
+        return jsonify({'message': f'Cleared {removed} episodes', 'removed': removed}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/history/stats', methods=['GET'])
+@jwt_required()
+def get_watch_stats():
+    """Totals, watch time, recent activity and per-month counts worked out from the watch history"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        now = datetime.utcnow()
+        week_ago = now - timedelta(days=7)
+        months = []
+        year, month = now.year, now.month
+        for _ in range(6):
+            months.insert(0, f"{year:04d}-{month:02d}")
+            year, month = (year, month - 1) if month > 1 else (year - 1, 12)
+        by_month = {m: {'movies': 0, 'episodes': 0} for m in months}
+
+        movies = episodes = in_progress = sessions_this_week = 0
+        watch_seconds = 0
+        percents = []
+        shows = {}
+        titles_this_week = set()
+        for r in watch_history_collection.find({'user_id': user_id}):
+            duration = r.get('duration_seconds')
+            if r.get('completed'):
+                if r['content_type'] == 'movie':
+                    movies += 1
+                else:
+                    episodes += 1
+                month = r.get('completed_at') and r['completed_at'].strftime('%Y-%m')
+                if month in by_month:
+                    by_month[month]['movies' if r['content_type'] == 'movie' else 'episodes'] += 1
+            elif r.get('progress_seconds'):
+                in_progress += 1
+            watch_seconds += duration if r.get('completed') and duration else min(r.get('progress_seconds', 0), duration or float('inf'))
+            if duration:
+                percents.append(100 if r.get('completed') else min(r.get('progress_seconds', 0) / duration, 1) * 100)
+            if r['last_watched_at'] >= week_ago:
+                titles_this_week.add((r['content_type'], r['content_id']))
+            sessions_this_week += sum(1 for s in r.get('sessions', []) if s['started_at'] >= week_ago)
+            if r['content_type'] == 'tv':
+                show = shows.setdefault(r['content_id'], {'content_id': r['content_id'], 'title': r.get('title'),
+                                                          'poster_path': r.get('poster_path'), 'episodes_watched': 0})
+                show['episodes_watched'] += 1 if r.get('completed') else 0
+
+        top_shows = sorted(shows.values(), key=lambda s: s['episodes_watched'], reverse=True)[:5]
+        return jsonify({
+            'movies_watched': movies,
+            'episodes_watched': episodes,
+            'shows_watched': len(shows),
+            'in_progress': in_progress,
+            'watch_time_seconds': int(watch_seconds),
+            'average_completion': round(sum(percents) / len(percents)) if percents else 0,
+            'titles_last_7_days': len(titles_this_week),
+            'sessions_last_7_days': sessions_this_week,
+            'by_month': [{'month': m, **by_month[m]} for m in months],
+            'top_shows': [s for s in top_shows if s['episodes_watched']]
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= CONTINUE WATCHING ROUTES =============
+
+@app.route('/api/continue-watching', methods=['GET'])
+@jwt_required()
+def get_continue_watching():
+    """Built from the watch history: the latest record per title, most recent first"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        items = []
+        for record in _continue_watching(user_id):
+            item = _history_response(record)
+            item.update({'progress': item['percent'] or 0, 'last_watched': record['last_watched_at']})
+            items.append(item)
+
+        return jsonify(items), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/continue-watching', methods=['POST'])
+@jwt_required()
+@validate_json(CONTINUE_WATCHING_SCHEMA)
+def update_continue_watching():
+    """Older clients: progress is a percentage, recorded in the watch history without a position"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
+        _migrate_user_continue_watching(user_id)
+
+        _record_progress(user_id, {
+            **{k: data.get(k) for k in ('content_id', 'content_type', 'title', 'poster_path', 'season', 'episode')},
+            'progress_percent': data.get('progress')
+        })
+
+        return jsonify({'message': 'Continue watching updated'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/continue-watching/<content_id>', methods=['DELETE'])
+@jwt_required()
+def remove_from_continue_watching(content_id):
+    """Hide a title from continue watching. Its history stays, and watching it again brings it back."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        watch_history_collection.update_many(
+            {'user_id': user_id, 'content_id': {'$in': _content_id_values(content_id)}},
+            {'$set': {'hidden': True}}
+        )
+
+        # Don't fail if item wasn't found - it might already be deleted
+        return jsonify({'message': 'Removed from continue watching'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= UP NEXT ROUTES =============
+
+# Which episode of each show a user follows should play next, worked out from their watch history
+# and the TMDB episode lists. Shows with nothing aired left to watch are caught up; shows left alone
+# for UP_NEXT_DROP_DAYS, or dropped by hand, are dropped until the user watches them again.
+
+UP_NEXT_DROP_DAYS = 90
+SCHEDULE_CACHE_SECONDS = 6 * 60 * 60
+SCHEDULE_CACHE_MAX_ENTRIES = 5000
+# TMDB fetches one Up Next request may make; the rest are served stale or filled in on later requests
+UP_NEXT_MAX_FETCHES = 20
+
+
+class FetchBudget:
+    """How many cache misses a request may still fetch from TMDB, and whether any were turned away"""
+    def __init__(self, fetches):
+        self.left = fetches
+        self.exhausted = False
+
+    def spend(self):
+        if self.left <= 0:
+            self.exhausted = True
+            return False
+        self.left -= 1
+        return True
+
+
+def _cached_schedule(key, load, budget=None):
+    cached = schedule_cache.get(key)
+    if cached:
+        schedule_cache.move_to_end(key)
+        if time.time() - cached[0] < SCHEDULE_CACHE_SECONDS:
+            return cached[1]
+    if budget is not None and not budget.spend():
+        return cached[1] if cached else None
+    data = load()
+    if data is not None:
+        schedule_cache[key] = (time.time(), data)
+        schedule_cache.move_to_end(key)
+        while len(schedule_cache) > SCHEDULE_CACHE_MAX_ENTRIES:
+            schedule_cache.popitem(last=False)
+    return data
+
+
+def _show_schedule(tv_id, budget=None):
+    """Title, status, season numbers (specials left out) and the latest and next episodes to air for a show, or None"""
+    def load():
+        show = tmdb.get_tv_details(tv_id)
+        if show.get('error'):
+            return None
+        upcoming = show.get('next_episode_to_air') or {}
+        aired = show.get('last_episode_to_air') or {}
+        return {
+            'title': show.get('name'),
+            'poster_path': show.get('poster_path'),
+            'status': show.get('status'),
+            'seasons': sorted(s['season_number'] for s in show.get('seasons', []) if s.get('season_number')),
+            'next_to_air': {
+                'season': upcoming['season_number'],
+                'episode': upcoming['episode_number'],
+                'name': upcoming.get('name'),
+                'air_date': upcoming.get('air_date')
+            } if upcoming.get('season_number') is not None else None,
+            'last_aired': {
+                'season': aired['season_number'],
+                'episode': aired['episode_number'],
+                'air_date': aired.get('air_date')
+            } if aired.get('season_number') is not None else None
+        }
+    return _cached_schedule(f"tv:{tv_id}", load, budget)
+
+
+def _season_episodes(tv_id, season_number, budget=None, strict=False):
+    """Episode numbers, names and air dates of one season. Empty if TMDB couldn't be asked, or None with strict."""
+    def load():
+        season = tmdb.get_tv_season_details(tv_id, season_number, include_ratings=False)
+        if season.get('error'):
+            return None
+        return [{
+            'season': season_number,
+            'episode': ep['episode_number'],
+            'name': ep.get('name'),
+            'air_date': ep.get('air_date'),
+            'runtime': ep.get('runtime'),
+            'still_path': ep.get('still_path'),
+            'overview': ep.get('overview')
+        } for ep in season.get('episodes', [])]
+    episodes = _cached_schedule(f"tv:{tv_id}:s{season_number}", load, budget)
+    return episodes if strict or episodes is not None else []
+
+
+def _episode_info(tv_id, season, episode, budget=None):
+    for ep in _season_episodes(tv_id, season, budget):
+        if ep['episode'] == episode:
+            return ep
+    return {'season': season, 'episode': episode, 'name': None, 'air_date': None}
+
+
+def _episode_after(tv_id, season, episode, budget=None):
+    """The episode TMDB lists after (season, episode), crossing into later seasons, aired or not. None at the end."""
+    schedule = _show_schedule(tv_id, budget)
+    for number in (schedule or {}).get('seasons', []):
+        if number < season:
+            continue
+        for ep in _season_episodes(tv_id, number, budget):
+            if number > season or ep['episode'] > episode:
+                return ep
+    return None
+
+
+def _has_aired(ep, today):
+    return bool(ep and ep.get('air_date') and ep['air_date'] <= today)
+
+
+def _up_next(user_id, budget=None):
+    """
+    Every show in the user's history sorted into (queue, caught_up, dropped). Shows are looked at
+    most recently watched first, so a budget that runs out leaves the long-idle ones until later.
+    """
+    now = datetime.utcnow()
+    today = now.strftime('%Y-%m-%d')
+    inactive_before = now - timedelta(days=UP_NEXT_DROP_DAYS)
+    tracking = {t['tv_id']: t for t in show_tracking_collection.find({'user_id': user_id})}
+
+    shows = watch_history_collection.aggregate([
+        {'$match': {'user_id': user_id, 'content_type': 'tv', 'episode': {'$ne': None}}},
+        {'$sort': {'season': -1, 'episode': -1}},
+        {'$group': {'_id': '$content_id', 'furthest': {'$first': '$$ROOT'}, 'last_watched_at': {'$max': '$last_watched_at'}}},
+        {'$sort': {'last_watched_at': -1}}
+    ])
+
+    queue, caught_up, dropped = [], [], []
+    for show in shows:
+        tv_id, furthest, last_watched_at = show['_id'], show['furthest'], show['last_watched_at']
+        if not isinstance(tv_id, int):
+            continue
+        schedule = _show_schedule(tv_id, budget) or {}
+        base = {
+            'content_id': tv_id,
+            'title': furthest.get('title') or schedule.get('title'),
+            'poster_path': furthest.get('poster_path') or schedule.get('poster_path'),
+            'last_season': furthest['season'],
+            'last_episode': furthest['episode'],
+            'last_watched_at': last_watched_at.isoformat()
+        }
+
+        resume = not furthest.get('completed')
+        if resume:
+            next_ep = _episode_info(tv_id, furthest['season'], furthest['episode'], budget)
+        else:
+            next_ep = _episode_after(tv_id, furthest['season'], furthest['episode'], budget)
+            if next_ep is None and budget is not None and budget.exhausted:
+                continue  # not fetched yet, so we can't tell whether they're caught up
+
+        if not resume and not _has_aired(next_ep, today):
+            upcoming = next_ep if next_ep and next_ep.get('air_date') else schedule.get('next_to_air')
+            caught_up.append({**base, 'status': schedule.get('status'), 'next_to_air': upcoming})
+            continue
+
+        # An episode that aired after the user finished the one before it is new to them
+        new_episode = not resume and next_ep['air_date'] > furthest['last_watched_at'].strftime('%Y-%m-%d')
+        track = tracking.get(tv_id, {})
+        manual_drop = bool(track.get('dropped_at')) and track['dropped_at'] >= last_watched_at
+        active_since = max(last_watched_at, track.get('kept_at') or last_watched_at)
+        if manual_drop or (active_since < inactive_before and not new_episode):
+            dropped.append({
+                **base,
+                'next': next_ep,
+                'reason': 'dropped' if manual_drop else 'inactive',
+                'dropped_at': (track['dropped_at'] if manual_drop else active_since + timedelta(days=UP_NEXT_DROP_DAYS)).isoformat()
+            })
+            continue
+
+        active_at = last_watched_at
+        if new_episode:
+            active_at = max(active_at, datetime.strptime(next_ep['air_date'], '%Y-%m-%d'))
+        queue.append((not resume, -active_at.timestamp(), {
+            **base,
+            **next_ep,
+            'resume': resume,
+            'progress_seconds': furthest.get('progress_seconds', 0) if resume else 0,
+            'duration_seconds': furthest.get('duration_seconds') if resume else None,
+            'new_episode': new_episode,
+            'new_season': new_episode and next_ep['season'] > furthest['season']
+        }))
+
+    queue.sort(key=lambda entry: entry[:2])
+    caught_up.sort(key=lambda s: ((s['next_to_air'] or {}).get('air_date') or '9999', s['title'] or ''))
+    dropped.sort(key=lambda s: s['last_watched_at'], reverse=True)
+    return [entry for _, _, entry in queue], caught_up, dropped
+
+
+@app.route('/api/up-next', methods=['GET'])
+@jwt_required()
+def get_up_next():
+    """
+    Returns queue (the next episode of every show being watched: unfinished episodes first,
+    then most recently active, with new_episode/new_season set when the show has added episodes
+    since the user caught up), caught_up and dropped. partial is true when some shows still
+    need fetching from TMDB and were left out; asking again shortly fills them in.
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        budget = FetchBudget(UP_NEXT_MAX_FETCHES)
+        queue, caught_up, dropped = _up_next(user_id, budget)
+        return jsonify({'queue': queue, 'caught_up': caught_up, 'dropped': dropped, 'partial': budget.exhausted}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/up-next/tv/<int:tv_id>', methods=['GET'])
+@jwt_required(optional=True)
+def get_show_up_next(tv_id):
+    """
+    The episode after ?season=&episode=, or after the furthest one the signed-in user has watched.
+    aired is false when it is announced but not out yet.
+    """
+    try:
+        season = request.args.get('season', type=int)
+        episode = request.args.get('episode', type=int)
+        if season is None or episode is None:
+            user_id = get_jwt_identity()
+            if not user_id:
+                return jsonify({'error': 'season and episode are required'}), 400
+            position = _viewer_episode({'_id': ObjectId(user_id)}, tv_id)
+            if not position:
+                first = _episode_after(tv_id, 0, 0)
+                return jsonify({'next': first, 'aired': _has_aired(first, datetime.utcnow().strftime('%Y-%m-%d'))}), 200
+            season, episode = position
+
+        next_ep = _episode_after(tv_id, season, episode)
+        return jsonify({'next': next_ep, 'aired': _has_aired(next_ep, datetime.utcnow().strftime('%Y-%m-%d'))}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/up-next/tv/<int:tv_id>/drop', methods=['POST'])
+@jwt_required()
+def drop_show(tv_id):
+    """Move a show out of the queue. Watching it again puts it back."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        show_tracking_collection.update_one(
+            {'user_id': user_id, 'tv_id': tv_id},
+            {'$set': {'dropped_at': datetime.utcnow()}, '$unset': {'kept_at': ''}},
+            upsert=True
+        )
+        return jsonify({'message': 'Show dropped'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/up-next/tv/<int:tv_id>/drop', methods=['DELETE'])
+@jwt_required()
+def undrop_show(tv_id):
+    """Put a dropped show back in the queue, including one that was dropped for going unwatched"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        show_tracking_collection.update_one(
+            {'user_id': user_id, 'tv_id': tv_id},
+            {'$set': {'kept_at': datetime.utcnow()}, '$unset': {'dropped_at': ''}},
+            upsert=True
+        )
+        return jsonify({'message': 'Show back in Up Next'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= RELEASE CALENDAR ROUTES =============
+
+# Upcoming movie releases and episode air dates for everything on a user's lists plus the shows
+# they are watching. It can be subscribed to as an iCalendar feed, and release-day notifications
+# are sent by the scheduled /api/admin/release-reminders job.
+
+CALENDAR_DEFAULT_DAYS = 60
+CALENDAR_MAX_DAYS = 365
+CALENDAR_FEED_PAST_DAYS = 30
+# TMDB fetches one calendar may make (Up Next's share included); the rest are served stale or left for a later request
+CALENDAR_MAX_FETCHES = 40
+CALENDAR_REMINDER_MAX_FETCHES = 150
+
+
+def _upcoming_release_dates(budget=None):
+    """{movie id: release date} for the month's theatrical releases from TMDB's upcoming list"""
+    def load():
+        dates = {}
+        for page in (1, 2, 3):
+            data = tmdb.get_upcoming_movies(page=page)
+            if data.get('error'):
+                break
+            dates.update({m['id']: m['release_date'] for m in data.get('results', []) if m.get('release_date')})
+            if page >= data.get('total_pages', 1):
+                break
+        return dates
+    return _cached_schedule('movies:upcoming', load, budget)
+
+
+def _movie_release(movie_id, budget=None):
+    """{'release_date': date or None}, or None when TMDB couldn't be asked"""
+    upcoming = _upcoming_release_dates(budget) or {}
+    if movie_id in upcoming:
+        return {'release_date': upcoming[movie_id]}
+
+    def load():
+        movie = tmdb.get_movie_details(movie_id)
+        return None if movie.get('error') else {'release_date': movie.get('release_date') or None}
+    return _cached_schedule(f"movie:{movie_id}", load, budget)
+
+
+def _calendar_titles(user_id, budget=None):
+    """{(content_type, id): {title, poster_path, source}} for the user's lists and the shows they are watching"""
+    titles = {}
+    for lst in lists_collection.find({'$or': [{'owner_id': user_id}, {'collaborators': user_id}]}, {'items': 1}):
+        for item in lst.get('items', []):
+            if str(item['content_id']).isdigit():
+                titles.setdefault((item['content_type'], int(item['content_id'])), {
+                    'title': item.get('title'), 'poster_path': item.get('poster_path'), 'source': 'watchlist'
+                })
+    queue, caught_up, _ = _up_next(user_id, budget)
+    for show in queue + caught_up:
+        titles.setdefault(('tv', show['content_id']), {
+            'title': show['title'], 'poster_path': show['poster_path'], 'source': 'watching'
+        })
+    return titles
+
+
+def _release_calendar(user_id, start, end, budget=None):
+    """
+    (entries, complete): releases and air dates from start to end (YYYY-MM-DD, both included),
+    soonest first. complete is False when some titles couldn't be looked up and are missing.
+    """
+    entries = []
+    complete = True
+    for (content_type, content_id), info in _calendar_titles(user_id, budget).items():
+        base = {'content_type': content_type, 'content_id': content_id, 'poster_path': info['poster_path'], 'source': info['source']}
+        if content_type == 'movie':
+            release = _movie_release(content_id, budget)
+            if release is None:
+                complete = False
+                continue
+            date = release['release_date']
+            if date and start <= date <= end:
+                entries.append({**base, 'key': f"movie:{content_id}", 'date': date, 'title': info['title'],
+                                'season': None, 'episode': None, 'episode_name': None})
+            continue
+
+        schedule = _show_schedule(content_id, budget)
+        if not schedule:
+            complete = False
+            continue
+        # Start at the season on air - the last aired episode covers a finale that aired today
+        seasons_from = [s['season'] for s in (schedule.get('next_to_air'), schedule.get('last_aired'))
+                        if s and s.get('air_date') and s['air_date'] >= start]
+        if not seasons_from:
+            continue
+        for number in schedule['seasons']:
+            if number < min(seasons_from):
+                continue
+            episodes = _season_episodes(content_id, number, budget, strict=True)
+            if episodes is None:
+                complete = False
+                continue
+            for ep in episodes:
+                if ep.get('air_date') and start <= ep['air_date'] <= end:
+                    entries.append({**base, 'key': _episode_key(content_id, number, ep['episode']), 'date': ep['air_date'],
+                                    'title': info['title'] or schedule['title'], 'season': number,
+                                    'episode': ep['episode'], 'episode_name': ep.get('name')})
+
+    entries.sort(key=lambda e: (e['date'], e['title'] or '', e['season'] or 0, e['episode'] or 0))
+    return entries, complete and not (budget and budget.exhausted)
+
+
+def _send_release_reminders(user_id):
+    """
+    Notify the user about anything on their calendar that comes out today. Runs at most once a day
+    per user: the day is only marked done once every title on the calendar could be looked up, so a
+    failed TMDB lookup is retried on the next run (notifications already sent aren't repeated).
+    Returns (notifications sent, whether the day is done).
+    """
+    today = datetime.utcnow().strftime('%Y-%m-%d')
+    if not users_collection.find_one({'_id': user_id, 'release_reminders_on': {'$ne': today}}, {'_id': 1}):
+        return 0, True
+    entries, complete = _release_calendar(user_id, today, today, FetchBudget(CALENDAR_REMINDER_MAX_FETCHES))
+    if complete and not users_collection.find_one_and_update(
+            {'_id': user_id, 'release_reminders_on': {'$ne': today}}, {'$set': {'release_reminders_on': today}}):
+        return 0, True  # another run got there first
+
+    sent = 0
+    for entry in entries:
+        doc = {
+            'user_id': user_id,
+            'type': 'release',
+            'release_key': entry['key'],
+            'content_id': entry['content_id'],
+            'content_type': entry['content_type'],
+            'title': entry['title'],
+            'season': entry['season'],
+            'episode': entry['episode'],
+            'read': False,
+            'created_at': datetime.utcnow()
+        }
+        result = notifications_collection.update_one(
+            {'user_id': user_id, 'type': 'release', 'release_key': entry['key']}, {'$setOnInsert': doc}, upsert=True
+        )
+        if result.upserted_id:
+            doc['_id'] = result.upserted_id
+            _push_to_user(str(user_id), 'notification', {
+                'notification': _notification_response(doc, {}),
+                'unread_count': notifications_collection.count_documents({'user_id': user_id, 'read': False})
+            })
+            sent += 1
+    return sent, complete
+
+
+def _ics_text(value):
+    return str(value or '').replace('\\', '\\\\').replace(';', '\\;').replace(',', '\\,').replace('\n', '\\n')
+
+
+def _ics_fold(line):
+    """Lines over 75 octets continue on the next line after a space (RFC 5545 section 3.1)"""
+    parts, current = [], ''
+    for ch in line:
+        if len((current + ch).encode('utf-8')) > (74 if parts else 75):
+            parts.append(current)
+            current = ''
+        current += ch
+    parts.append(current)
+    return '\r\n '.join(parts)
+
+
+def _calendar_ics(user_id, entries):
+    stamp = datetime.utcnow().strftime('%Y%m%dT%H%M%SZ')
+    lines = [
+        'BEGIN:VCALENDAR',
+        'VERSION:2.0',
+        'PRODID:-//GlitchBox//Release Calendar//EN',
+        'CALSCALE:GREGORIAN',
+        'METHOD:PUBLISH',
+        'X-WR-CALNAME:GlitchBox releases',
+        'REFRESH-INTERVAL;VALUE=DURATION:PT12H'
+    ]
+    for entry in entries:
+        day = datetime.strptime(entry['date'], '%Y-%m-%d')
+        if entry['content_type'] == 'tv':
+            summary = f"{entry['title'] or 'Untitled'} S{entry['season']}E{entry['episode']}"
+            if entry['episode_name']:
+                summary += f" - {entry['episode_name']}"
+            description = 'New episode'
+        else:
+            summary = entry['title'] or 'Untitled'
+            description = 'Release day'
+        lines += [
+            'BEGIN:VEVENT',
+            f"UID:{entry['key'].replace(':', '-')}-{user_id}@glitchbox",
+            f'DTSTAMP:{stamp}',
+            f"DTSTART;VALUE=DATE:{day.strftime('%Y%m%d')}",
+            f"DTEND;VALUE=DATE:{(day + timedelta(days=1)).strftime('%Y%m%d')}",
+            f'SUMMARY:{_ics_text(summary)}',
+            f"DESCRIPTION:{_ics_text(description + (' (on your watchlist)' if entry['source'] == 'watchlist' else ''))}",
+            'TRANSP:TRANSPARENT',
+            'END:VEVENT'
+        ]
+    lines.append('END:VCALENDAR')
+    return '\r\n'.join(_ics_fold(line) for line in lines) + '\r\n'
+
+
+def _calendar_feed_url(token):
+    return f"{_app_base_url()}/api/calendar/{token}.ics"
+
+
+@app.route('/api/calendar', methods=['GET'])
+@jwt_required()
+def get_release_calendar():
+    """
+    Upcoming releases for the next ?days= (default 60, max 365), soonest first. partial is true when
+    some titles still need fetching from TMDB and were left out; asking again shortly fills them in.
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        _migrate_user_continue_watching(user_id)
+
+        days = min(CALENDAR_MAX_DAYS, max(1, request.args.get('days', CALENDAR_DEFAULT_DAYS, type=int)))
+        today = datetime.utcnow()
+        start, end = today.strftime('%Y-%m-%d'), (today + timedelta(days=days)).strftime('%Y-%m-%d')
+        budget = FetchBudget(CALENDAR_MAX_FETCHES)
+        entries, _ = _release_calendar(user_id, start, end, budget)
+
+        user = users_collection.find_one({'_id': user_id}, {'calendar_token': 1})
+        token = (user or {}).get('calendar_token')
+        return jsonify({
+            'start': start,
+            'end': end,
+            'entries': [{k: v for k, v in e.items() if k != 'key'} for e in entries],
+            'subscription_url': _calendar_feed_url(token) if token else None,
+            'partial': budget.exhausted
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/calendar/subscription', methods=['POST'])
+@jwt_required()
+def create_calendar_subscription():
+    """Make a private .ics feed URL for calendar apps. Calling it again replaces the old URL."""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        token = secrets.token_urlsafe(24)
+        users_collection.update_one({'_id': user_id}, {'$set': {'calendar_token': token}})
+        url = _calendar_feed_url(token)
+        return jsonify({'url': url, 'webcal_url': re.sub(r'^https?://', 'webcal://', url)}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/calendar/subscription', methods=['DELETE'])
+@jwt_required()
+def delete_calendar_subscription():
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        users_collection.update_one({'_id': user_id}, {'$unset': {'calendar_token': ''}})
+        return jsonify({'message': 'Calendar feed turned off'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/calendar/<token>.ics', methods=['GET'])
+def get_calendar_feed(token):
+    """The iCalendar feed behind a subscription URL: the last month and the coming year"""
+    try:
+        user = users_collection.find_one({'calendar_token': token}, {'_id': 1, 'status': 1, 'suspended_until': 1})
+        if not user or _account_block_reason(user):
+            return jsonify({'error': 'Calendar not found'}), 404
+
+        today = datetime.utcnow()
+        entries, _ = _release_calendar(
+            user['_id'],
+            (today - timedelta(days=CALENDAR_FEED_PAST_DAYS)).strftime('%Y-%m-%d'),
+            (today + timedelta(days=CALENDAR_MAX_DAYS)).strftime('%Y-%m-%d'),
+            FetchBudget(CALENDAR_MAX_FETCHES)
+        )
+        response = Response(_calendar_ics(user['_id'], entries), mimetype='text/calendar')
+        response.headers['Content-Disposition'] = 'inline; filename="glitchbox-releases.ics"'
+        return response
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/admin/release-reminders', methods=['POST'])
+def send_release_reminders():
+    """
+    Send today's release notifications to everyone who hasn't had them yet. This is the only place
+    reminders are sent, so point a daily scheduler (cron or similar) at it.
+
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    """
+    try:
+        if not _migration_authorized():
+            return jsonify({'error': 'Unauthorized'}), 401
+
+        user_ids = set(watch_history_collection.distinct('user_id', {'content_type': 'tv'}))
+        user_ids.update(lists_collection.distinct('owner_id', {'items.0': {'$exists': True}}))
+        user_ids.update(lists_collection.distinct('collaborators', {'items.0': {'$exists': True}}))
+
+        notified = sent = incomplete = failed = 0
+        for user_id in user_ids:
+            # One user's bad data or a TMDB hiccup mustn't stop everyone after them
+            try:
+                count, done = _send_release_reminders(user_id)
+            except Exception as e:
+                print(f"[Calendar] Release reminders failed for {user_id}: {e}")
+                failed += 1
+                continue
+            sent += count
+            notified += 1 if count else 0
+            incomplete += 0 if done else 1
+
+        return jsonify({
+            'success': True,
+            'users_checked': len(user_ids),
+            'users_notified': notified,
+            'notifications_sent': sent,
+            # Not every title could be looked up for these; running the job again picks up the rest
+            'users_incomplete': incomplete,
+            'users_failed': failed
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= FAVORITES (LIVE CHANNELS) ROUTES =============
+
+@app.route('/api/favorites', methods=['GET'])
+@jwt_required()
+def get_favorites():
+    try:
+        user_id = get_jwt_identity()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
+
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        # Get favorites from user document (default to empty array)
+        favorites = user.get('favorites', [])
+
+        return jsonify(favorites), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/favorites', methods=['POST'])
+@jwt_required()
+@validate_json(FAVORITE_SCHEMA)
+def add_to_favorites():
+    try:
+        user_id = get_jwt_identity()
+        data = request.get_json()
+
+        favorite_item = {
+            'channel_id': data['channel_id'],
+            'channel_name': data['channel_name'],
+            'added_at': datetime.utcnow()
+        }
+
+        # First ensure the user has a favorites array
+        users_collection.update_one(
+            {'_id': ObjectId(user_id), 'favorites': {'$exists': False}},
+            {'$set': {'favorites': []}}
+        )
+
+        # Check if already in favorites and add if not
+        result = users_collection.update_one(
+            {
+                '_id': ObjectId(user_id),
+                'favorites.channel_id': {'$ne': data['channel_id']}
+            },
+            {
+                '$push': {'favorites': favorite_item}
+            }
+        )
+
+        if result.matched_count == 0:
+            # Either user not found or channel already in favorites
+            user = users_collection.find_one({'_id': ObjectId(user_id)})
+            if not user:
+                return jsonify({'error': 'User not found'}), 404
+            return jsonify({'error': 'Channel already in favorites'}), 400
+
+        return jsonify(favorite_item), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/favorites/<path:channel_id>', methods=['DELETE'])
+@jwt_required()
+def remove_from_favorites(channel_id):
+    try:
+        user_id = get_jwt_identity()
+
+        # Remove channel from favorites array
+        result = users_collection.update_one(
+            {'_id': ObjectId(user_id)},
+            {
+                '$pull': {
+                    'favorites': {'channel_id': channel_id}
+                }
+            }
+        )
+
+        if result.matched_count == 0:
+            return jsonify({'error': 'User not found'}), 404
+
+        if result.modified_count == 0:
+            return jsonify({'error': 'Channel not found in favorites'}), 404
+
+        return jsonify({'message': 'Removed from favorites'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+@app.route('/api/favorites/remove', methods=['POST'])
+@jwt_required()
+@validate_json(FAVORITE_REMOVE_SCHEMA)
+def remove_from_favorites_post():
+    try:
+        user_id = get_jwt_identity()
+        data = request.get_json()
+        channel_id = data.get('channel_id')
+
+        if not channel_id:
+            return jsonify({'error': 'channel_id is required'}), 400
+
+        result = users_collection.update_one(
+            {'_id': ObjectId(user_id)},
+            {
+                '$pull': {
+                    'favorites': {'channel_id': channel_id}
+                }
+            }
+        )
+
+        if result.matched_count == 0:
+            return jsonify({'error': 'User not found'}), 404
+
+        if result.modified_count == 0:
+            return jsonify({'error': 'Channel not found in favorites'}), 404
+
+        return jsonify({'message': 'Removed from favorites'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= FRIENDS ROUTES =============
+# Blocking and muting are stored as ObjectId arrays on the user document (blocked_users, muted_users).
+# A block works in both directions: neither side can find, befriend or read the other.
+# A mute is one-sided and only quiets comments and feed activity.
+
+def _id_list(values):
+    return [ObjectId(v) if not isinstance(v, ObjectId) else v for v in (values or [])]
+
+
+def _blocked_by(user_id):
+    """Ids of users who have blocked user_id"""
+    return [u['_id'] for u in users_collection.find({'blocked_users': ObjectId(user_id)}, {'_id': 1})]
+
+
+def _is_blocked_between(a, b):
+    """True if either user has blocked the other"""
+    a, b = ObjectId(a), ObjectId(b)
+    return users_collection.count_documents({'$or': [
+        {'_id': a, 'blocked_users': b},
+        {'_id': b, 'blocked_users': a}
+    ]}, limit=1) > 0
+
+
+def _hidden_author_ids(user):
+    """Authors whose comments and activity a user shouldn't see: blocked either way, or muted"""
+    return list({*_id_list(user.get('blocked_users')), *_id_list(user.get('muted_users')), *_blocked_by(user['_id'])})
+
+
+@app.route('/api/friends', methods=['GET'])
+@jwt_required()
+def get_friends():
+    try:
+        user_id = get_jwt_identity()
+        print(f"[Friends] get_friends called for user_id: {user_id}")
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
+
+        if not user:
+            print(f"[Friends] User not found: {user_id}")
+            return jsonify({'error': 'User not found'}), 404
+
+        friend_ids = _friend_ids(user['_id'])
+        print(f"[Friends] User {user.get('username')} has {len(friend_ids)} friends")
+        friends = list(users_collection.find({'_id': {'$in': friend_ids}}))
+        print(f"[Friends] Found {len(friends)} friend documents")
+        if friends:
+            print(f"[Friends] Friend usernames: {[f.get('username') for f in friends]}")
+
+        muted = set(_id_list(user.get('muted_users')))
+        friends_list = [{
+            'id': str(friend['_id']),
+            'username': friend['username'],
+            'muted': friend['_id'] in muted,
+            'display_name': (friend.get('profile') or {}).get('display_name') or friend['username'],
+            'avatar_url': _avatar_url(friend.get('profile') or {}),
+            'online': str(friend['_id']) in online_users,
+            'watching': _presence_payload(str(friend['_id']))['watching']
+        } for friend in friends]
+
+        print(f"[Friends] Returning friends: {[f['username'] for f in friends_list]}")
+        return jsonify(friends_list), 200
+
+    except Exception as e:
+        print(f"[Friends] Error in get_friends: {str(e)}")
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/search', methods=['GET'])
+@jwt_required()
+@rate_limit('friend_search', per_account=get_jwt_identity)
+def search_users():
+    try:
+        query = request.args.get('q', '')
+        user_id = get_jwt_identity()
+
+        print(f"[Friends] Search request - Query: '{query}', User ID: {user_id}")
+
+        if not query:
+            print("[Friends] Empty query, returning empty array")
+            return jsonify([]), 200
+
+        # Get total user count for debugging
+        total_users = users_collection.count_documents({})
+        print(f"[Friends] Total users in database: {total_users}")
+
+        # Anyone who has blocked the searcher stays invisible to them
+        users = list(users_collection.find({
+            'username': {'$regex': query, '$options': 'i'},
+            '_id': {'$ne': ObjectId(user_id)},
+            'blocked_users': {'$ne': ObjectId(user_id)}
+        }).limit(10))
+
+        print(f"[Friends] Found {len(users)} users matching '{query}'")
+
+        results = [{
+            'id': str(user['_id']),
+            'username': user['username']
+        } for user in users]
+
+        print(f"[Friends] Returning results: {[u['username'] for u in results]}")
+
+        return jsonify(results), 200
+
+    except Exception as e:
+        print(f"[Friends] Error in search_users: {str(e)}")
+        return jsonify({'error': str(e)}), 500
+
+
+# Taste matching needs at least this many titles rated by both users
+SUGGESTION_MIN_SHARED_RATINGS = 3
+# Only this many of the viewer's most recent ratings are compared
+SUGGESTION_RATINGS_SAMPLE = 200
+# At most this many of other people's ratings of those titles are read
+SUGGESTION_CANDIDATE_RATINGS_MAX = 5000
+# Ratings are centered on the middle of the 1-10 scale so shared dislikes count as agreement
+RATING_MIDPOINT = 5.5
+
+
+def _taste_similarity(mine, theirs):
+    """
+    Cosine similarity of two users' centered ratings over the titles both rated, plus how many
+    of those they rated within a point and a half of each other.
+    """
+    shared = [key for key in theirs if key in mine]
+    a = [mine[key] - RATING_MIDPOINT for key in shared]
+    b = [theirs[key] - RATING_MIDPOINT for key in shared]
+    norm = math.sqrt(sum(x * x for x in a)) * math.sqrt(sum(y * y for y in b))
+    similarity = sum(x * y for x, y in zip(a, b)) / norm if norm else 0.0
+    agreed = sum(1 for key in shared if abs(mine[key] - theirs[key]) <= 1.5)
+    return similarity, len(shared), agreed
+
+
+def _suggestion_reason(mutual, agreed):
+    parts = []
+    if mutual:
+        parts.append(f"{mutual} mutual friend{'s' if mutual != 1 else ''}")
+    if agreed:
+        parts.append(f"you both rated {agreed} title{'s' if agreed != 1 else ''} similarly")
+    return ' · '.join(parts)
+
+
+@app.route('/api/friends/suggestions', methods=['GET'])
+@jwt_required()
+def get_friend_suggestions():
+    """
+    People you might know, ranked by mutual friends and by how closely your ratings match.
+    Friends, pending requests, blocked users (either way) and private profiles are left out.
+    Only whole movies and shows are compared, not episodes. ?limit= (max 30).
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        user = users_collection.find_one({'_id': user_id}, {'blocked_users': 1})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        limit = min(30, max(1, request.args.get('limit', 10, type=int)))
+
+        friend_ids = set(_friend_ids(user_id))
+        excluded = {user_id, *friend_ids, *_id_list(user.get('blocked_users')), *_blocked_by(user_id)}
+        for fr in friend_requests_collection.find(
+                {'status': 'pending', '$or': [{'from_user_id': user_id}, {'to_user_id': user_id}]},
+                {'from_user_id': 1, 'to_user_id': 1}):
+            excluded.update((fr['from_user_id'], fr['to_user_id']))
+
+        # Friends of friends
+        mutual = {}
+        if friend_ids:
+            ids = list(friend_ids)
+            for fr in friend_requests_collection.find(
+                    {'status': 'accepted', '$or': [{'from_user_id': {'$in': ids}}, {'to_user_id': {'$in': ids}}]},
+                    {'from_user_id': 1, 'to_user_id': 1}):
+                for friend, other in ((fr['from_user_id'], fr['to_user_id']), (fr['to_user_id'], fr['from_user_id'])):
+                    if friend in friend_ids and other not in excluded:
+                        mutual.setdefault(other, set()).add(friend)
+
+        # Everyone else who rated the same titles
+        mine = {r['content_key']: float(r['rating']) for r in ratings_collection.find(
+            {'user_id': user_id, 'episode': {'$exists': False}},
+            {'content_key': 1, 'rating': 1}).sort('updated_at', -1).limit(SUGGESTION_RATINGS_SAMPLE)}
+        theirs = {}
+        if len(mine) >= SUGGESTION_MIN_SHARED_RATINGS:
+            for r in ratings_collection.find({'content_key': {'$in': list(mine)}, 'user_id': {'$nin': list(excluded)}},
+                                             {'user_id': 1, 'content_key': 1, 'rating': 1}).limit(SUGGESTION_CANDIDATE_RATINGS_MAX):
+                theirs.setdefault(r['user_id'], {})[r['content_key']] = float(r['rating'])
+
+        # People with private profiles aren't suggested
+        pool = set(mutual) | set(theirs)
+        pool -= {u['_id'] for u in users_collection.find(
+            {'_id': {'$in': list(pool)}, 'profile.visibility': 'private'}, {'_id': 1})}
+
+        scored = {}
+        for candidate in pool:
+            similarity, shared, agreed = 0.0, 0, 0
+            if len(theirs.get(candidate, {})) >= SUGGESTION_MIN_SHARED_RATINGS:
+                similarity, shared, agreed = _taste_similarity(mine, theirs[candidate])
+            mutual_count = len(mutual.get(candidate, ()))
+            # A mutual friend counts for a point; a close taste match over ten or more titles is worth about five
+            score = mutual_count + max(similarity, 0.0) * min(shared, 10) / 2
+            if score > 0:
+                scored[candidate] = (score, mutual_count, similarity, shared, agreed)
+
+        ranked = sorted(scored.items(), key=lambda item: item[1][0], reverse=True)[:limit * 2]
+        candidates = {u['_id']: u for u in users_collection.find(
+            {'_id': {'$in': [c for c, _ in ranked]}},
+            {'username': 1, 'profile': 1, 'status': 1, 'suspended_until': 1})}
+
+        suggestions = []
+        for candidate_id, (score, mutual_count, similarity, shared, agreed) in ranked:
+            candidate = candidates.get(candidate_id)
+            if not candidate or _account_block_reason(candidate):
+                continue
+            profile = candidate.get('profile') or {}
+            names = sorted(u['username'] for u in users_collection.find(
+                {'_id': {'$in': list(mutual.get(candidate_id, ()))[:3]}}, {'username': 1}))
+            suggestions.append({
+                'id': str(candidate_id),
+                'username': candidate['username'],
+                'display_name': profile.get('display_name') or candidate['username'],
+                'avatar_url': _avatar_url(profile),
+                'mutual_friends': mutual_count,
+                'mutual_friend_names': names,
+                'shared_ratings': shared,
+                'similarity': round(similarity, 3),
+                'score': round(score, 3),
+                'reason': _suggestion_reason(mutual_count, agreed) or 'similar taste'
+            })
+            if len(suggestions) == limit:
+                break
+
+        return jsonify(suggestions), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/request', methods=['POST'])
+@jwt_required()
+@validate_json(FRIEND_REQUEST_SCHEMA)
+def send_friend_request():
+    try:
+        user_id = get_jwt_identity()
+        data = request.get_json()
+        to_username = data.get('username')
+
+        if not to_username:
+            return jsonify({'error': 'Username is required'}), 400
+
+        to_user = users_collection.find_one({'username': to_username})
+
+        if not to_user:
+            return jsonify({'error': 'User not found'}), 404
+
+        to_user_id = to_user['_id']
+
+        # Someone who blocked you looks like they don't exist
+        if ObjectId(user_id) in _id_list(to_user.get('blocked_users')):
+            return jsonify({'error': 'User not found'}), 404
+
+        # Check if already friends
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
+        if to_user_id in _id_list(user.get('blocked_users')):
+            return jsonify({'error': 'Unblock this user before sending a friend request'}), 400
+        if _are_friends(user['_id'], to_user_id):
+            return jsonify({'error': 'Already friends'}), 400
+
+        # Check if request already exists
+        existing_request = friend_requests_collection.find_one({
+            '$or': [
+                {'from_user_id': ObjectId(user_id), 'to_user_id': to_user_id, 'status': 'pending'},
+                {'from_user_id': to_user_id, 'to_user_id': ObjectId(user_id), 'status': 'pending'}
+            ]
+        })
+
+        if existing_request:
+            return jsonify({'error': 'Friend request already pending'}), 400
+
+        # Create friend request
+        friend_request = {
+            'from_user_id': ObjectId(user_id),
+            'to_user_id': to_user_id,
+            'status': 'pending',
+            'created_at': datetime.utcnow()
+        }
+
+        friend_requests_collection.insert_one(friend_request)
+        _notify(to_user_id, 'friend_request', user, request_id=friend_request['_id'])
+        _push_to_user(str(to_user_id), 'friend_requests_changed', {})
+
+        return jsonify({'message': 'Friend request sent'}), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/requests', methods=['GET'])
+@jwt_required()
+def get_friend_requests():
+    try:
+        user_id = get_jwt_identity()
+
+        # Get pending requests sent to this user
+        requests = list(friend_requests_collection.find({
+            'to_user_id': ObjectId(user_id),
+            'status': 'pending'
+        }))
+
+        # Get sender info
+        result = []
+        for req in requests:
+            sender = users_collection.find_one({'_id': req['from_user_id']})
+            if sender:
+                result.append({
+                    'id': str(req['_id']),
+                    'from_user': {
+                        'id': str(sender['_id']),
+                        'username': sender['username']
+                    },
+                    'created_at': req['created_at'].isoformat()
+                })
+
+        return jsonify(result), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/requests/<request_id>/accept', methods=['POST'])
+@jwt_required()
+def accept_friend_request(request_id):
+    try:
+        user_id = get_jwt_identity()
+
+        friend_request = friend_requests_collection.find_one({
+            '_id': ObjectId(request_id),
+            'to_user_id': ObjectId(user_id),
+            'status': 'pending'
+        })
+
+        if not friend_request:
+            return jsonify({'error': 'Friend request not found'}), 404
+
+        from_user_id = friend_request['from_user_id']
+
+        # The accepted request is the friendship
+        friend_requests_collection.update_one(
+            {'_id': ObjectId(request_id)},
+            {'$set': {'status': 'accepted', 'accepted_at': datetime.utcnow()}}
+        )
+
+        _notify(from_user_id, 'friend_accept', users_collection.find_one({'_id': ObjectId(user_id)}))
+        for uid in (user_id, str(from_user_id)):
+            _push_to_user(uid, 'friends_changed', {})
+        _push_to_user(user_id, 'friend_requests_changed', {})
+        # Each side can see the other's presence from now on
+        for a, b in ((user_id, str(from_user_id)), (str(from_user_id), user_id)):
+            if a in online_users and b in online_users:
+                _push_to_user(a, 'presence', _presence_payload(b))
+        # The request itself has been dealt with
+        notifications_collection.update_many(
+            {'user_id': ObjectId(user_id), 'type': 'friend_request', 'request_id': ObjectId(request_id)},
+            {'$set': {'read': True, 'read_at': datetime.utcnow()}}
+        )
+
+        return jsonify({'message': 'Friend request accepted'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/requests/<request_id>/reject', methods=['POST'])
+@jwt_required()
+def reject_friend_request(request_id):
+    try:
+        user_id = get_jwt_identity()
+
+        friend_request = friend_requests_collection.find_one({
+            '_id': ObjectId(request_id),
+            'to_user_id': ObjectId(user_id),
+            'status': 'pending'
+        })
+
+        if not friend_request:
+            return jsonify({'error': 'Friend request not found'}), 404
+
+        # Update request status
+        friend_requests_collection.update_one(
+            {'_id': ObjectId(request_id)},
+            {'$set': {'status': 'rejected'}}
+        )
+        notifications_collection.update_many(
+            {'user_id': ObjectId(user_id), 'type': 'friend_request', 'request_id': ObjectId(request_id)},
+            {'$set': {'read': True, 'read_at': datetime.utcnow()}}
+        )
+        _push_to_user(user_id, 'friend_requests_changed', {})
+
+        return jsonify({'message': 'Friend request rejected'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/<friend_id>', methods=['DELETE'])
+@jwt_required()
+def remove_friend(friend_id):
+    try:
+        user_id = get_jwt_identity()
+
+        result = friend_requests_collection.delete_many(_friendship_filter(user_id, friend_id))
+        if not result.deleted_count:
+            return jsonify({'error': 'Not friends'}), 404
+
+        _end_collaboration(user_id, friend_id)
+        for uid in (user_id, friend_id):
+            _push_to_user(uid, 'friends_changed', {})
+
+        return jsonify({'message': 'Friend removed'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# list name in the URL -> field on the user document
+USER_LISTS = {'blocked': 'blocked_users', 'muted': 'muted_users'}
+
+
+@app.route('/api/friends/<list_name>', methods=['GET'])
+@jwt_required()
+def get_user_list(list_name):
+    """Users you've blocked (/api/friends/blocked) or muted (/api/friends/muted)"""
+    if list_name not in USER_LISTS:
+        return jsonify({'error': 'Not found'}), 404
+    try:
+        user = users_collection.find_one({'_id': ObjectId(get_jwt_identity())}, {USER_LISTS[list_name]: 1})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        listed = users_collection.find({'_id': {'$in': _id_list(user.get(USER_LISTS[list_name]))}}, {'username': 1, 'profile': 1})
+        return jsonify([{
+            'id': str(u['_id']),
+            'username': u['username'],
+            'display_name': (u.get('profile') or {}).get('display_name') or u['username'],
+            'avatar_url': _avatar_url(u.get('profile') or {})
+        } for u in listed]), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/<list_name>', methods=['POST'])
+@jwt_required()
+@validate_json(FRIEND_REQUEST_SCHEMA)
+def add_to_user_list(list_name):
+    """
+    Block or mute a user by username. Blocking also ends any friendship, takes each of
+    you off the other's lists and withdraws pending friend requests between the two of you.
+    """
+    if list_name not in USER_LISTS:
+        return jsonify({'error': 'Not found'}), 404
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        target = users_collection.find_one({'username': request.get_json()['username']}, {'username': 1})
+        if not target:
+            return jsonify({'error': 'User not found'}), 404
+        if target['_id'] == user_id:
+            return jsonify({'error': f"You can't {'block' if list_name == 'blocked' else 'mute'} yourself"}), 400
+
+        users_collection.update_one({'_id': user_id}, {'$addToSet': {USER_LISTS[list_name]: target['_id']}})
+
+        if list_name == 'blocked':
+            friend_requests_collection.delete_many(_friendship_filter(user_id, target['_id']))
+            _end_collaboration(user_id, target['_id'])
+            pending = list(friend_requests_collection.find({'status': 'pending', '$or': [
+                {'from_user_id': user_id, 'to_user_id': target['_id']},
+                {'from_user_id': target['_id'], 'to_user_id': user_id}
+            ]}, {'_id': 1}))
+            if pending:
+                request_ids = [r['_id'] for r in pending]
+                friend_requests_collection.delete_many({'_id': {'$in': request_ids}})
+                notifications_collection.delete_many({'type': 'friend_request', 'request_id': {'$in': request_ids}})
+            for uid in (user_id, target['_id']):
+                _push_to_user(str(uid), 'friends_changed', {})
+                _push_to_user(str(uid), 'friend_requests_changed', {})
+            print(f"[Friends] {user_id} blocked {target['username']}")
+
+        return jsonify({'message': f"{target['username']} {list_name}", 'id': str(target['_id'])}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/friends/<list_name>/<target_id>', methods=['DELETE'])
+@jwt_required()
+def remove_from_user_list(list_name, target_id):
+    """Unblock or unmute. Unblocking doesn't restore a friendship."""
+    if list_name not in USER_LISTS:
+        return jsonify({'error': 'Not found'}), 404
+    try:
+        result = users_collection.update_one(
+            {'_id': ObjectId(get_jwt_identity())},
+            {'$pull': {USER_LISTS[list_name]: ObjectId(target_id)}}
+        )
+        if not result.modified_count:
+            return jsonify({'error': f'User is not {list_name}'}), 404
+        return jsonify({'message': f"User un{list_name}"}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= ACTIVITY FEED ROUTES =============
+
+# activity type -> the sharing toggle that controls it
+ACTIVITY_TYPES = {
+    'watching': 'watching',
+    'watchlist_add': 'watchlist',
+    'rating': 'ratings',
+    'comment': 'comments'
+}
+
+
+def _activity_sharing(user):
+    """Which kinds of activity a user shares with friends (nothing until they opt in)"""
+    sharing = user.get('activity_sharing') or {}
+    return {key: sharing.get(key, False) for key in ACTIVITY_SHARING_KEYS}
+
+
+def _parse_activity_time(value):
+    """Ratings store ISO strings, everything else datetimes"""
+    if isinstance(value, str):
+        try:
+            return datetime.fromisoformat(value)
+        except ValueError:
+            return None
+    return value
+
+
+def _watching_activity(user_ids, before, limit):
+    """Newest watch history records for these users"""
+    if not user_ids:
+        return []
+    query = {'user_id': {'$in': user_ids}, 'last_watched_at': {'$lt': before} if before else {'$type': 'date'}}
+    return [(r['last_watched_at'], r['user_id'], {**r, 'progress': _history_response(r)['percent']})
+            for r in watch_history_collection.find(query, {'sessions': 0}).sort('last_watched_at', -1).limit(limit)]
+
+
+def _list_activity(user_ids, viewer_id, friend_ids, before, limit):
+    """Newest items these users added to lists the viewer is allowed to see"""
+    if not user_ids:
+        return []
+    visible = [
+        {'privacy': 'public'},
+        {'privacy': 'friends', 'owner_id': {'$in': [*friend_ids, viewer_id]}},
+        {'owner_id': viewer_id},
+        {'collaborators': viewer_id}
+    ]
+    pipeline = [
+        {'$match': {'items.added_by': {'$in': user_ids}, '$or': visible}},
+        {'$unwind': '$items'},
+        {'$match': {'items.added_by': {'$in': user_ids}, 'items.added_at': {'$lt': before} if before else {'$type': 'date'}}},
+        {'$sort': {'items.added_at': -1}},
+        {'$limit': limit},
+        {'$project': {'item': '$items', 'list_name': '$name', 'list_id': '$_id'}}
+    ]
+    return [(doc['item']['added_at'], doc['item']['added_by'], {**doc['item'], 'list_name': doc['list_name'], 'list_id': doc['list_id']})
+            for doc in lists_collection.aggregate(pipeline)]
+
+
+@app.route('/api/feed', methods=['GET'])
+@jwt_required()
+def get_activity_feed():
+    """
+    What friends have been watching, rating, adding to watchlists and commenting on, newest first.
+    ?types=watching,watchlist_add,rating,comment to filter, ?limit= (max 50), ?before= the previous page's next_cursor.
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        user = users_collection.find_one({'_id': user_id})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        limit = min(50, max(1, request.args.get('limit', 20, type=int)))
+        types = set(ACTIVITY_TYPES)
+        if request.args.get('types'):
+            types &= set(request.args['types'].split(','))
+
+        before = None
+        if request.args.get('before'):
+            try:
+                before = datetime.fromisoformat(request.args['before'])
+            except ValueError:
+                return jsonify({'error': 'Invalid cursor'}), 400
+
+        hidden = set(_hidden_author_ids(user))
+        friend_ids = [f for f in _friend_ids(user_id) if f not in hidden]
+        friends = {f['_id']: f for f in users_collection.find(
+            {'_id': {'$in': friend_ids}}, {'username': 1, 'profile': 1, 'activity_sharing': 1})}
+
+        def sharing(activity_type):
+            """Friends who share this kind of activity"""
+            setting = ACTIVITY_TYPES[activity_type]
+            return [fid for fid, f in friends.items() if _activity_sharing(f)[setting]] if activity_type in types else []
+
+        # Pull up to limit+1 of each kind, then merge - the newest limit+1 overall are among them
+        fetch = limit + 1
+        items = []
+        for at, fid, item in _watching_activity(sharing('watching'), before, fetch):
+            items.append((at, fid, 'watching', item))
+        for at, fid, item in _list_activity(sharing('watchlist_add'), user_id, friend_ids, before, fetch):
+            items.append((at, fid, 'watchlist_add', item))
+
+        rating_ids = sharing('rating')
+        if rating_ids:
+            query = {'user_id': {'$in': rating_ids}}
+            if before:
+                query['$or'] = [{'updated_at': {'$lt': before.isoformat()}}, {'updated_at': {'$lt': before}}]
+            for rating in ratings_collection.find(query).sort('updated_at', -1).limit(fetch):
+                at = _parse_activity_time(rating.get('updated_at'))
+                if at:
+                    items.append((at, rating['user_id'], 'rating', rating))
+
+        comment_ids = sharing('comment')
+        if comment_ids:
+            query = {'user_id': {'$in': comment_ids}, 'deleted': {'$ne': True}, 'hidden': {'$ne': True}}
+            if before:
+                query['created_at'] = {'$lt': before}
+            for comment in comments_collection.find(query).sort('created_at', -1).limit(fetch):
+                items.append((comment['created_at'], comment['user_id'], 'comment', comment))
+
+        items.sort(key=lambda i: i[0], reverse=True)
+        next_cursor = None
+        if len(items) > limit:
+            items = items[:limit]
+            next_cursor = items[-1][0].isoformat()
+
+        activities = []
+        for at, fid, activity_type, item in items:
+            friend = friends[fid]
+            profile = friend.get('profile') or {}
+            activity = {
+                'type': activity_type,
+                'at': at.isoformat(),
+                'user': {
+                    'id': str(fid),
+                    'username': friend['username'],
+                    'display_name': profile.get('display_name') or friend['username'],
+                    'avatar_url': _avatar_url(profile)
+                }
+            }
+
+            if activity_type == 'rating':
+                content_type, content_id = _content_from_key(item['content_key'])
+                activity['rating'] = item['rating']
+                if item.get('episode') is not None:
+                    activity.update({'season': item['season'], 'episode': item['episode']})
+                if item.get('review'):
+                    activity['review_title'] = item['review']['title']
+            else:
+                content_type, content_id = item['content_type'], item['content_id']
+            summary = {'title': item.get('title'), 'poster_path': item.get('poster_path')}
+            if not summary['title']:
+                summary = get_content_summary(content_type, content_id)
+            activity['content'] = {'content_id': content_id, 'content_type': content_type, **summary}
+
+            if activity_type == 'watching':
+                activity.update({'season': item.get('season'), 'episode': item.get('episode'), 'progress': item.get('progress')})
+            elif activity_type == 'watchlist_add':
+                activity.update({'list_name': item.get('list_name'), 'list_id': str(item['list_id'])})
+            elif activity_type == 'comment':
+                comment = _mark_collapsed(
+                    {'user_id': str(fid), 'spoiler': item.get('spoiler'), 'season': item.get('season'), 'episode': item.get('episode')},
+                    str(user_id), _viewer_episode(user, content_id)
+                )
+                activity.update({
+                    'comment_id': str(item['_id']),
+                    'comment_text': item['comment_text'],
+                    'is_reply': item.get('parent_comment_id') is not None,
+                    'collapsed': comment['collapsed'],
+                    'collapse_reason': comment['collapse_reason']
+                })
+            activities.append(activity)
+
+        return jsonify({'activities': activities, 'next_cursor': next_cursor}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/feed/settings', methods=['GET'])
+@jwt_required()
+def get_activity_sharing():
+    try:
+        user = users_collection.find_one({'_id': ObjectId(get_jwt_identity())}, {'activity_sharing': 1})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        return jsonify(_activity_sharing(user)), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/feed/settings', methods=['PUT'])
+@jwt_required()
+@validate_json(ACTIVITY_SHARING_SCHEMA)
+def update_activity_sharing():
+    """Turn sharing of each kind of activity with friends on or off"""
+    try:
+        data = request.get_json()
+        updates = {f'activity_sharing.{key}': data[key] for key in ACTIVITY_SHARING_KEYS if key in data}
+        if not updates:
+            return jsonify({'error': f"Nothing to update - send any of: {', '.join(ACTIVITY_SHARING_KEYS)}"}), 400
+
+        user = users_collection.find_one_and_update(
+            {'_id': ObjectId(get_jwt_identity())},
+            {'$set': updates},
+            return_document=ReturnDocument.AFTER
+        )
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        # Online friends stop (or start) seeing what's being watched straight away
+        sharing = _activity_sharing(user)
+        entry = online_users.get(str(user['_id']))
+        if entry is not None and entry.get('shares_watching') != sharing['watching']:
+            entry['shares_watching'] = sharing['watching']
+            _broadcast_presence(str(user['_id']))
+
+        return jsonify(sharing), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# ============= COMMENTS ROUTES =============
+
+MODERATION_FIELDS = ('report_count', 'hidden_by', 'hidden_reason', 'hidden_at', 'deleted_by', 'deleted_reason')
+
+
+def _is_removed(comment):
+    return bool(comment.get('deleted') or comment.get('hidden'))
+
+
+def _visible_comments_filter(user):
+    """
+    Friends' comments, anyone's public ones, and removed comments whoever wrote them
+    (those are masked and only kept if a visible reply hangs off them).
+    Nothing from people you've muted or blocked, or who have blocked you
+    """
+    return {'$and': [
+        {'$or': [
+            {'user_id': {'$in': _friend_ids(user['_id']) + [user['_id']]}},
+            {'visibility': 'public'},
+            {'deleted': True},
+            {'hidden': True}
+        ]},
+        {'user_id': {'$nin': _hidden_author_ids(user)}}
+    ]}
+
+
+def _visible_comment(comment_id, user, projection=None):
+    """
+    The comment if get_comments would show it to this user: it passes the visibility filter,
+    isn't removed, and so does every comment above it in its thread. None otherwise
+    """
+    visible = _visible_comments_filter(user)
+    comment = comments_collection.find_one({'_id': comment_id, **visible}, projection)
+    if not comment or _is_removed(comment):
+        return None
+    path = comment.get('path') or []
+    if path and comments_collection.count_documents({'_id': {'$in': path}, **visible}) != len(path):
+        return None
+    return comment
+
+
+def _present_comment(comment):
+    """
+    Strip moderation bookkeeping and mask deleted/hidden comments. Removed comments keep
+    their place in the tree so replies stay readable, but lose their text and author.
+    """
+    for field in MODERATION_FIELDS:
+        comment.pop(field, None)
+    if comment.get('hidden'):
+        comment['comment_text'] = '[removed by moderator]'
+    elif comment.get('deleted'):
+        comment['comment_text'] = '[deleted]'
+    if _is_removed(comment):
+        comment['username'] = '[deleted]'
+        comment['user_id'] = None
+        comment['edited'] = False
+    if comment.get('edited_at'):
+        comment['edited_at'] = comment['edited_at'].isoformat()
+    return comment
+
+
+def _is_moderator(user_id):
+    user = users_collection.find_one({'_id': ObjectId(user_id)}, {'role': 1})
+    return ROLE_LEVELS.get(_user_role(user or {}), 0) >= ROLE_LEVELS['moderator']
+
+
+def _serialize_comment(comment):
+    comment['_id'] = str(comment['_id'])
+    comment['user_id'] = str(comment['user_id']) if comment.get('user_id') else None
+    for field in ('parent_comment_id', 'thread_root_id'):
+        if comment.get(field):
+            comment[field] = str(comment[field])
+    comment.pop('path', None)
+    comment['created_at'] = comment['created_at'].isoformat()
+    return _present_comment(comment)
+
+
+# sort name -> (field, direction); _id breaks ties so cursors are stable
+COMMENT_SORTS = {
+    'newest': ('created_at', -1),
+    'oldest': ('created_at', 1),
+    'most_liked': ('like_count', -1)
+}
+
+
+# Emoji reactions offered alongside likes, by the name used in URLs
+COMMENT_REACTIONS = {
+    'laugh': '😂',
+    'love': '😍',
+    'wow': '😮',
+    'sad': '😢',
+    'angry': '😡',
+    'fire': '🔥'
+}
+
+
+def _comment_engagement_stages(viewer_id):
+    """
+    Aggregation stages adding like_count and liked_by_user, plus reactions
+    ([{reaction, emoji, count}] for every reaction used) and my_reactions.
+    """
+    return [
+        {'$lookup': {'from': comment_likes_collection.name, 'localField': '_id', 'foreignField': 'comment_id', 'as': 'likes'}},
+        {'$lookup': {'from': comment_reactions_collection.name, 'localField': '_id', 'foreignField': 'comment_id', 'as': 'reaction_docs'}},
+        {'$addFields': {
+            'like_count': {'$size': '$likes'},
+            'liked_by_user': {'$in': [viewer_id, '$likes.user_id']},
+            'reactions': {'$filter': {
+                'input': [
+                    {'reaction': name, 'emoji': emoji, 'count': {'$size': {'$filter': {
+                        'input': '$reaction_docs',
+                        'cond': {'$eq': ['$$this.reaction', name]}
+                    }}}}
+                    for name, emoji in COMMENT_REACTIONS.items()
+                ],
+                'cond': {'$gt': ['$$this.count', 0]}
+            }},
+            'my_reactions': {'$map': {
+                'input': {'$filter': {'input': '$reaction_docs', 'cond': {'$eq': ['$$this.user_id', viewer_id]}}},
+                'in': '$$this.reaction'
+            }}
+        }},
+        {'$project': {'likes': 0, 'reaction_docs': 0}}
+    ]
+
+
+def _encode_comment_cursor(value, comment_id):
+    if isinstance(value, datetime):
+        value = value.isoformat()
+    return base64.urlsafe_b64encode(json.dumps([value, str(comment_id)]).encode()).decode()
+
+
+def _decode_comment_cursor(cursor, sort_field):
+    try:
+        value, comment_id = json.loads(base64.urlsafe_b64decode(cursor.encode()))
+        if sort_field == 'created_at':
+            value = datetime.fromisoformat(value)
+        return value, ObjectId(comment_id)
+    except Exception:
+        raise ValueError('Invalid cursor')
+
+
+def _viewer_episode(user, content_id):
+    """Furthest (season, episode) the user has played of a show according to their watch history, or None"""
+    record = watch_history_collection.find_one(
+        {'user_id': user['_id'], 'content_type': 'tv', 'content_id': {'$in': _content_id_values(str(content_id))},
+         'episode': {'$ne': None}},
+        {'season': 1, 'episode': 1}, sort=[('season', -1), ('episode', -1)]
+    )
+    return (record['season'], record['episode']) if record else None
+
+
+def _mark_collapsed(comment, viewer_id, progress):
+    """
+    Flag comments (and their replies) that should start collapsed for this viewer: spoilers,
+    and anything anchored to an episode they haven't reached yet. Your own never collapse.
+    """
+    reason = None
+    if comment.get('user_id') != viewer_id and not _is_removed(comment):
+        anchor = (comment.get('season'), comment.get('episode'))
+        if anchor[0] is not None and anchor[1] is not None and (progress is None or progress < anchor):
+            reason = 'unwatched_episode'
+        elif comment.get('spoiler'):
+            reason = 'spoiler'
+    comment['collapsed'] = reason is not None
+    comment['collapse_reason'] = reason
+    for reply in comment.get('replies', []):
+        _mark_collapsed(reply, viewer_id, progress)
+    return comment
+
+
+def _attach_replies(node, thread):
+    """Nest a thread's flat, oldest-first replies under node, dropping removed leaves"""
+    children = {}
+    for reply in thread:
+        children.setdefault(reply.get('parent_comment_id'), []).append(reply)
+
+    def attach(parent):
+        kept = []
+        for reply in children.get(parent['_id'], []):
+            attach(reply)
+            if _is_removed(reply) and not reply['replies']:
+                continue
+            kept.append(_serialize_comment(reply))
+        parent['replies'] = kept
+
+    attach(node)
+
+
+@app.route('/api/comments/<content_id>', methods=['GET'])
+@jwt_required()
+def get_comments(content_id):
+    """
+    A page of top-level comments, each with its whole reply thread, in one aggregation.
+    ?sort=newest|oldest|most_liked, ?limit= (max 50), ?cursor= is the previous page's next_cursor.
+    """
+    try:
+        user_id = ObjectId(get_jwt_identity())
+
+        # Convert content_id to int (it's stored as integer in DB)
+        try:
+            content_id_int = int(content_id)
+        except ValueError:
+            content_id_int = content_id  # Fallback to string if not a number
+
+        user = users_collection.find_one({'_id': user_id})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        sort = request.args.get('sort', 'newest')
+        if sort not in COMMENT_SORTS:
+            return jsonify({'error': f"sort must be one of {', '.join(COMMENT_SORTS)}"}), 400
+        sort_field, direction = COMMENT_SORTS[sort]
+        limit = min(50, max(1, request.args.get('limit', 20, type=int)))
+
+        visible = _visible_comments_filter(user)
+
+        pipeline = [
+            {'$match': {'content_id': content_id_int, 'parent_comment_id': None, **visible}},
+            *_comment_engagement_stages(user_id)
+        ]
+
+        cursor = request.args.get('cursor')
+        if cursor:
+            try:
+                after_value, after_id = _decode_comment_cursor(cursor, sort_field)
+            except ValueError:
+                return jsonify({'error': 'Invalid cursor'}), 400
+            op = '$lt' if direction < 0 else '$gt'
+            pipeline.append({'$match': {'$or': [
+                {sort_field: {op: after_value}},
+                {sort_field: after_value, '_id': {op: after_id}}
+            ]}})
+
+        pipeline += [
+            {'$sort': {sort_field: direction, '_id': direction}},
+            {'$limit': limit + 1},
+            {'$lookup': {
+                'from': comments_collection.name,
+                'let': {'root': '$_id'},
+                'pipeline': [
+                    {'$match': {'$expr': {'$and': [
+                        {'$eq': ['$thread_root_id', '$$root']},
+                        {'$ne': ['$_id', '$$root']}
+                    ]}}},
+                    {'$match': visible},
+                    *_comment_engagement_stages(user_id),
+                    {'$sort': {'created_at': 1}}
+                ],
+                'as': 'thread'
+            }}
+        ]
+
+        roots = list(comments_collection.aggregate(pipeline))
+        next_cursor = None
+        if len(roots) > limit:
+            roots = roots[:limit]
+            next_cursor = _encode_comment_cursor(roots[-1][sort_field], roots[-1]['_id'])
+
+        progress = _viewer_episode(user, content_id_int)
+        comments = []
+        for root in roots:
+            _attach_replies(root, root.pop('thread'))
+            if _is_removed(root) and not root['replies']:
+                continue
+            comments.append(_mark_collapsed(_serialize_comment(root), str(user_id), progress))
+
+        print(f"[Comments] {len(comments)} threads for content {content_id_int} (sort={sort}, more={next_cursor is not None})")
+
+        return jsonify({'comments': comments, 'sort': sort, 'next_cursor': next_cursor}), 200
+
+    except Exception as e:
+        print(f"[Comments] Error: {str(e)}")
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments', methods=['POST'])
+@jwt_required()
+@validate_json(COMMENT_SCHEMA, check=_comment_anchor_checks)
+def add_comment():
+    try:
+        user_id = get_jwt_identity()
+        data = request.get_json()
+
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
+
+        # Every comment records its thread root and ancestor path so a thread loads with one query
+        comment_oid = ObjectId()
+        thread_root_id, path = comment_oid, []
+        visibility = data.get('visibility')
+        season, episode = data.get('season'), data.get('episode')
+        if data.get('parent_comment_id'):
+            parent = _visible_comment(
+                ObjectId(data['parent_comment_id']), user,
+                {'thread_root_id': 1, 'path': 1, 'content_id': 1, 'deleted': 1, 'hidden': 1,
+                 'visibility': 1, 'season': 1, 'episode': 1}
+            )
+            if not parent or str(parent.get('content_id')) != str(data['content_id']):
+                return jsonify({'error': 'Parent comment not found'}), 404
+            thread_root_id = parent.get('thread_root_id') or parent['_id']
+            path = (parent.get('path') or []) + [parent['_id']]
+            # Replies follow the thread they're in unless told otherwise
+            visibility = visibility or parent.get('visibility')
+            if season is None:
+                season, episode = parent.get('season'), parent.get('episode')
+
+        mentioned = _resolve_mentions(data['comment_text'], user, visibility or 'friends')
+
+        comment = {
+            '_id': comment_oid,
+            'thread_root_id': thread_root_id,
+            'path': path,
+            'depth': len(path),
+            'parent_comment_id': ObjectId(data['parent_comment_id']) if data.get('parent_comment_id') else None,
+            'user_id': ObjectId(user_id),
+            'username': user['username'],
+            'content_id': data['content_id'],
+            'content_type': data['content_type'],
+            'comment_text': data['comment_text'],
+            'visibility': visibility or 'friends',
+            'spoiler': bool(data.get('spoiler')),
+            'season': season,
+            'episode': episode,
+            'mentions': [u['_id'] for u in mentioned],
+            'created_at': datetime.utcnow()
+        }
+
+        print(f"[Comments] Adding comment: user_id={comment['user_id']}, content_id={comment['content_id']}, text={comment['comment_text'][:50]}...")
+
+        comments_collection.insert_one(comment)
+
+        # A reply notifies the parent's author if they can see it; mentions notify everyone else named
+        replied_to = None
+        if comment['parent_comment_id']:
+            parent_author = comments_collection.find_one({'_id': comment['parent_comment_id']}, {'user_id': 1})
+            replied_to = parent_author.get('user_id') if parent_author else None
+            if replied_to and (comment['visibility'] == 'public' or _are_friends(comment['user_id'], replied_to)):
+                _notify(replied_to, 'reply', user, comment=comment)
+        for mentioned_user in mentioned:
+            if mentioned_user['_id'] != replied_to:
+                _notify(mentioned_user['_id'], 'mention', user, comment=comment)
+
+        _comments_changed(comment, 'added')
+
+        comment.pop('mentions')
+        comment.update({'like_count': 0, 'liked_by_user': False, 'reactions': [], 'my_reactions': [], 'replies': []})
+        _serialize_comment(comment)
+
+        print(f"[Comments] Comment saved with ID: {comment['_id']}")
+
+        return jsonify(comment), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>', methods=['PUT'])
+@jwt_required()
+@validate_json(COMMENT_EDIT_SCHEMA)
+def edit_comment(comment_id):
+    try:
+        user_id = get_jwt_identity()
+        data = request.get_json()
+
+        comment = comments_collection.find_one({
+            '_id': ObjectId(comment_id),
+            'user_id': ObjectId(user_id)
+        })
+        if not comment or _is_removed(comment):
+            return jsonify({'error': 'Comment not found or unauthorized'}), 404
+
+        text_changed = comment['comment_text'] != data['comment_text']
+        updates = {}
+        if data.get('spoiler') is not None and data['spoiler'] != comment.get('spoiler', False):
+            updates['spoiler'] = data['spoiler']
+        if not text_changed and not updates:
+            return jsonify({'error': 'Comment is unchanged'}), 400
+
+        if text_changed:
+            # Keep the text being replaced so moderators can review what was said
+            now = datetime.utcnow()
+            comment_revisions_collection.insert_one({
+                'comment_id': comment['_id'],
+                'user_id': comment['user_id'],
+                'comment_text': comment['comment_text'],
+                'written_at': comment.get('edited_at') or comment['created_at'],
+                'replaced_at': now
+            })
+            updates.update({'comment_text': data['comment_text'], 'edited': True, 'edited_at': now})
+
+        comment = comments_collection.find_one_and_update(
+            {'_id': comment['_id']},
+            {'$set': updates},
+            return_document=ReturnDocument.AFTER
+        )
+        _comments_changed(comment, 'edited')
+
+        return jsonify(_serialize_comment(comment)), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>/revisions', methods=['GET'])
+@jwt_required()
+def get_comment_revisions(comment_id):
+    """Edit history, oldest first. Visible to the author and to moderators."""
+    try:
+        user_id = get_jwt_identity()
+
+        comment = comments_collection.find_one({'_id': ObjectId(comment_id)})
+        if not comment:
+            return jsonify({'error': 'Comment not found'}), 404
+        if comment.get('user_id') != ObjectId(user_id) and not _is_moderator(user_id):
+            return jsonify({'error': 'Comment not found or unauthorized'}), 404
+
+        revisions = comment_revisions_collection.find({'comment_id': comment['_id']}).sort('replaced_at', 1)
+        return jsonify({
+            'comment_id': comment_id,
+            'current_text': comment['comment_text'],
+            'revisions': [{
+                'comment_text': r['comment_text'],
+                'written_at': r['written_at'].isoformat(),
+                'replaced_at': r['replaced_at'].isoformat()
+            } for r in revisions]
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>', methods=['DELETE'])
+@jwt_required()
+def delete_comment(comment_id):
+    """Soft delete: the comment stays in its thread as "[deleted]" so replies keep their context"""
+    try:
+        user_id = get_jwt_identity()
+
+        comment = comments_collection.find_one_and_update(
+            {
+                '_id': ObjectId(comment_id),
+                'user_id': ObjectId(user_id),
+                'deleted': {'$ne': True}
+            },
+            {'$set': {'deleted': True, 'deleted_at': datetime.utcnow(), 'deleted_by': 'author'}}
+        )
+
+        if not comment:
+            return jsonify({'error': 'Comment not found or unauthorized'}), 404
+        _comments_changed(comment, 'deleted')
+
+        # Reports against a comment its author removed need no further action
+        comment_reports_collection.update_many(
+            {'comment_id': ObjectId(comment_id), 'status': 'open'},
+            {'$set': {'status': 'resolved', 'resolution': 'deleted_by_author', 'resolved_at': datetime.utcnow()}}
+        )
+
+        return jsonify({'message': 'Comment deleted'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>/report', methods=['POST'])
+@jwt_required()
+@validate_json(COMMENT_REPORT_SCHEMA)
+def report_comment(comment_id):
+    try:
+        user_id = get_jwt_identity()
+        data = request.get_json()
+
+        comment = comments_collection.find_one({'_id': ObjectId(comment_id)})
+        if not comment or _is_removed(comment):
+            return jsonify({'error': 'Comment not found'}), 404
+        if comment.get('user_id') == ObjectId(user_id):
+            return jsonify({'error': 'You cannot report your own comment'}), 400
+
+        try:
+            comment_reports_collection.insert_one({
+                'comment_id': comment['_id'],
+                'reporter_id': ObjectId(user_id),
+                'reason': data['reason'],
+                'details': (data.get('details') or '').strip() or None,
+                'status': 'open',
+                'created_at': datetime.utcnow()
+            })
+        except DuplicateKeyError:
+            return jsonify({'error': 'You have already reported this comment'}), 400
+
+        comments_collection.update_one({'_id': comment['_id']}, {'$inc': {'report_count': 1}})
+
+        return jsonify({'message': 'Report submitted. A moderator will review it.'}), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>/like', methods=['POST'])
+@jwt_required()
+def like_comment(comment_id):
+    try:
+        user_id = get_jwt_identity()
+        user = users_collection.find_one({'_id': ObjectId(user_id)})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        comment = _visible_comment(ObjectId(comment_id), user)
+        if not comment:
+            return jsonify({'error': 'Comment not found'}), 404
+
+        # Check if already liked
+        existing_like = comment_likes_collection.find_one({
+            'comment_id': ObjectId(comment_id),
+            'user_id': ObjectId(user_id)
+        })
+
+        if existing_like:
+            return jsonify({'error': 'Already liked'}), 400
+
+        # Add like
+        comment_likes_collection.insert_one({
+            'comment_id': ObjectId(comment_id),
+            'user_id': ObjectId(user_id),
+            'created_at': datetime.utcnow()
+        })
+
+        if comment.get('user_id'):
+            _notify(comment['user_id'], 'comment_like', user, comment=comment)
+
+        # Get new like count
+        like_count = comment_likes_collection.count_documents({'comment_id': ObjectId(comment_id)})
+
+        return jsonify({'like_count': like_count}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>/like', methods=['DELETE'])
+@jwt_required()
+def unlike_comment(comment_id):
+    try:
+        user_id = get_jwt_identity()
+
+        # Remove like
+        result = comment_likes_collection.delete_one({
+            'comment_id': ObjectId(comment_id),
+            'user_id': ObjectId(user_id)
+        })
+
+        if result.deleted_count == 0:
+            return jsonify({'error': 'Like not found'}), 404
+
+        # Get new like count
+        like_count = comment_likes_collection.count_documents({'comment_id': ObjectId(comment_id)})
+
+        return jsonify({'like_count': like_count}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+def _reaction_summary(comment_id, user_id):
+    counts = {r['_id']: r['count'] for r in comment_reactions_collection.aggregate([
+        {'$match': {'comment_id': comment_id}},
+        {'$group': {'_id': '$reaction', 'count': {'$sum': 1}}}
+    ])}
+    mine = [r['reaction'] for r in comment_reactions_collection.find({'comment_id': comment_id, 'user_id': user_id}, {'reaction': 1})]
+    return {
+        'reactions': [{'reaction': name, 'emoji': emoji, 'count': counts[name]}
+                      for name, emoji in COMMENT_REACTIONS.items() if counts.get(name)],
+        'my_reactions': mine
+    }
+
+
+@app.route('/api/comments/<comment_id>/reactions/<reaction>', methods=['POST'])
+@jwt_required()
+def add_comment_reaction(comment_id, reaction):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        if reaction not in COMMENT_REACTIONS:
+            return jsonify({'error': f"reaction must be one of: {', '.join(COMMENT_REACTIONS)}"}), 400
+
+        user = users_collection.find_one({'_id': user_id})
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+
+        comment = _visible_comment(ObjectId(comment_id), user)
+        if not comment:
+            return jsonify({'error': 'Comment not found'}), 404
+
+        try:
+            comment_reactions_collection.insert_one({
+                'comment_id': comment['_id'],
+                'user_id': user_id,
+                'reaction': reaction,
+                'created_at': datetime.utcnow()
+            })
+        except DuplicateKeyError:
+            return jsonify({'error': 'Already reacted'}), 400
+
+        if comment.get('user_id'):
+            _notify(comment['user_id'], 'comment_reaction', user, comment=comment, reaction=reaction)
+
+        return jsonify(_reaction_summary(comment['_id'], user_id)), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/comments/<comment_id>/reactions/<reaction>', methods=['DELETE'])
+@jwt_required()
+def remove_comment_reaction(comment_id, reaction):
+    try:
+        user_id = ObjectId(get_jwt_identity())
+
+        result = comment_reactions_collection.delete_one({
+            'comment_id': ObjectId(comment_id),
+            'user_id': user_id,
+            'reaction': reaction
+        })
+        if result.deleted_count == 0:
+            return jsonify({'error': 'Reaction not found'}), 404
+
+        return jsonify(_reaction_summary(ObjectId(comment_id), user_id)), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+# ============= COMMENT MODERATION ROUTES =============
+
+def _resolve_reports(comment_id, status, resolution):
+    return comment_reports_collection.update_many(
+        {'comment_id': comment_id, 'status': 'open'},
+        {'$set': {
+            'status': status,
+            'resolution': resolution,
+            'resolved_by': g.current_user['_id'],
+            'resolved_at': datetime.utcnow()
+        }}
+    ).modified_count
+
+
+@app.route('/api/moderation/reports', methods=['GET'])
+@jwt_required()
+@role_required('moderator')
+def moderation_queue():
+    """Reported comments grouped per comment, most reported first. ?status=open|resolved|dismissed"""
+    try:
+        status = request.args.get('status', 'open')
+        if status not in ('open', 'resolved', 'dismissed'):
+            return jsonify({'error': 'status must be open, resolved or dismissed'}), 400
+        page = max(1, request.args.get('page', 1, type=int))
+        per_page = min(100, max(1, request.args.get('per_page', 25, type=int)))
+
+        pipeline = [
+            {'$match': {'status': status}},
+            {'$group': {
+                '_id': '$comment_id',
+                'report_count': {'$sum': 1},
+                'reasons': {'$push': {'reason': '$reason', 'details': '$details', 'created_at': '$created_at'}},
+                'first_reported_at': {'$min': '$created_at'},
+                'last_reported_at': {'$max': '$created_at'}
+            }},
+            {'$sort': {'report_count': -1, 'last_reported_at': -1}},
+            {'$facet': {
+                'items': [{'$skip': (page - 1) * per_page}, {'$limit': per_page}],
+                'total': [{'$count': 'count'}]
+            }}
+        ]
+        result = list(comment_reports_collection.aggregate(pipeline))
+        facet = result[0] if result else {'items': [], 'total': []}
+
+        comment_ids = [item['_id'] for item in facet['items']]
+        comments = {c['_id']: c for c in comments_collection.find({'_id': {'$in': comment_ids}})}
+        author_ids = [c['user_id'] for c in comments.values() if c.get('user_id')]
+        authors = {u['_id']: u for u in users_collection.find({'_id': {'$in': author_ids}}, {'username': 1, 'warning_count': 1, 'status': 1})}
+
+        items = []
+        for item in facet['items']:
+            comment = comments.get(item['_id'])
+            if not comment:
+                continue
+            author = authors.get(comment.get('user_id'), {})
+            items.append({
+                'comment_id': str(comment['_id']),
+                'comment_text': comment['comment_text'],
+                'content_id': comment.get('content_id'),
+                'content_type': comment.get('content_type'),
+                'created_at': comment['created_at'].isoformat(),
+                'edited': comment.get('edited', False),
+                'deleted': comment.get('deleted', False),
+                'hidden': comment.get('hidden', False),
+                'author': {
+                    'id': str(comment['user_id']) if comment.get('user_id') else None,
+                    'username': author.get('username', comment.get('username')),
+                    'status': author.get('status', 'active'),
+                    'warning_count': author.get('warning_count', 0)
+                },
+                'report_count': item['report_count'],
+                'reports': [{
+                    'reason': r['reason'],
+                    'details': r.get('details'),
+                    'created_at': r['created_at'].isoformat()
+                } for r in item['reasons']],
+                'first_reported_at': item['first_reported_at'].isoformat(),
+                'last_reported_at': item['last_reported_at'].isoformat()
+            })
+
+        return jsonify({
+            'reports': items,
+            'page': page,
+            'per_page': per_page,
+            'total': facet['total'][0]['count'] if facet['total'] else 0
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/moderation/comments/<comment_id>/hide', methods=['POST'])
+@jwt_required()
+@role_required('moderator')
+@validate_json(MODERATION_ACTION_SCHEMA)
+def moderation_hide_comment(comment_id):
+    try:
+        reason = (request.get_json().get('reason') or '').strip() or None
+        comment = comments_collection.find_one({'_id': ObjectId(comment_id)})
+        if not comment:
+            return jsonify({'error': 'Comment not found'}), 404
+        if not _can_moderate(comment):
+            return jsonify({'error': 'You cannot moderate this comment'}), 403
+
+        comments_collection.update_one({'_id': comment['_id']}, {'$set': {
+            'hidden': True,
+            'hidden_at': datetime.utcnow(),
+            'hidden_by': g.current_user['_id'],
+            'hidden_reason': reason
+        }})
+        resolved = _resolve_reports(comment['_id'], 'resolved', 'hidden')
+        _comments_changed(comment, 'hidden')
+        _audit('hide_comment', comment.get('user_id'), {'comment_id': comment_id, 'reason': reason, 'reports_resolved': resolved})
+
+        return jsonify({'message': 'Comment hidden', 'reports_resolved': resolved}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/moderation/comments/<comment_id>/unhide', methods=['POST'])
+@jwt_required()
+@role_required('moderator')
+def moderation_unhide_comment(comment_id):
+    try:
+        comment = comments_collection.find_one(
+            {'_id': ObjectId(comment_id), 'hidden': True}, {'user_id': 1, 'content_id': 1, 'content_type': 1}
+        )
+        if not comment:
+            return jsonify({'error': 'Comment not found or not hidden'}), 404
+        if not _can_moderate(comment):
+            return jsonify({'error': 'You cannot moderate this comment'}), 403
+
+        result = comments_collection.update_one(
+            {'_id': comment['_id'], 'hidden': True},
+            {'$set': {'hidden': False}, '$unset': {'hidden_at': '', 'hidden_by': '', 'hidden_reason': ''}}
+        )
+        if result.matched_count == 0:
+            return jsonify({'error': 'Comment not found or not hidden'}), 404
+
+        _comments_changed(comment, 'unhidden')
+        _audit('unhide_comment', comment.get('user_id'), {'comment_id': comment_id})
+        return jsonify({'message': 'Comment restored'}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/moderation/comments/<comment_id>/dismiss', methods=['POST'])
+@jwt_required()
+@role_required('moderator')
+def moderation_dismiss_reports(comment_id):
+    """Close every open report on a comment without taking action"""
+    try:
+        comment = comments_collection.find_one({'_id': ObjectId(comment_id)}, {'user_id': 1})
+        if not comment:
+            return jsonify({'error': 'Comment not found'}), 404
+        if not _can_moderate(comment):
+            return jsonify({'error': 'You cannot moderate this comment'}), 403
+
+        dismissed = _resolve_reports(comment['_id'], 'dismissed', 'no_action')
+        if dismissed == 0:
+            return jsonify({'error': 'No open reports for this comment'}), 404
+
+        _audit('dismiss_reports', comment.get('user_id'), {'comment_id': comment_id, 'reports_dismissed': dismissed})
+        return jsonify({'message': 'Reports dismissed', 'reports_dismissed': dismissed}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/moderation/users/<user_id>/warn', methods=['POST'])
+@jwt_required()
+@role_required('moderator')
+@validate_json(USER_WARNING_SCHEMA)
+def moderation_warn_user(user_id):
+    try:
+        data = request.get_json()
+        target = _find_target_user(user_id)
+        if not target:
+            return jsonify({'error': 'User not found'}), 404
+        if not _can_manage(target):
+            return jsonify({'error': 'You cannot warn this user'}), 403
+
+        comment_id = ObjectId(data['comment_id']) if data.get('comment_id') else None
+        if comment_id:
+            comment = comments_collection.find_one({'_id': comment_id}, {'user_id': 1})
+            if not comment or comment.get('user_id') != target['_id']:
+                return jsonify({'error': 'Comment not found for this user'}), 404
+            _resolve_reports(comment_id, 'resolved', 'author_warned')
+
+        user_warnings_collection.insert_one({
+            'user_id': target['_id'],
+            'moderator_id': g.current_user['_id'],
+            'reason': data['reason'].strip(),
+            'comment_id': comment_id,
+            'acknowledged': False,
+            'created_at': datetime.utcnow()
+        })
+        target = users_collection.find_one_and_update(
+            {'_id': target['_id']},
+            {'$inc': {'warning_count': 1}},
+            return_document=ReturnDocument.AFTER
+        )
+        _audit('warn_user', target['_id'], {'reason': data['reason'].strip(), 'comment_id': data.get('comment_id')})
+
+        return jsonify({'message': f"Warning sent to {target['username']}", 'warning_count': target['warning_count']}), 201
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/account/warnings', methods=['GET'])
+@jwt_required()
+def get_my_warnings():
+    """Warnings issued to the current user; viewing them marks them acknowledged"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        warnings = list(user_warnings_collection.find({'user_id': user_id}).sort('created_at', -1))
+        user_warnings_collection.update_many({'user_id': user_id, 'acknowledged': False}, {'$set': {'acknowledged': True}})
+
+        return jsonify([{
+            'id': str(w['_id']),
+            'reason': w['reason'],
+            'comment_id': str(w['comment_id']) if w.get('comment_id') else None,
+            'acknowledged': w.get('acknowledged', False),
+            'created_at': w['created_at'].isoformat()
+        } for w in warnings]), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+# ============= NOTIFICATIONS =============
+
+NOTIFICATION_TYPES = ('reply', 'mention', 'comment_like', 'comment_reaction', 'friend_request', 'friend_accept', 'list_collaborator', 'release')
+MENTION_PATTERN = re.compile(r'(?<![A-Za-z0-9_.-])@([A-Za-z0-9_.-]{3,30})')
+MAX_MENTIONS = 10
+
+
+def _resolve_mentions(text, author, visibility):
+    """
+    Users @mentioned in a comment who can actually see it: anyone who hasn't blocked the
+    author for public comments, otherwise only the author's friends.
+    """
+    names = list(dict.fromkeys(m.rstrip('.') for m in MENTION_PATTERN.findall(text)))[:MAX_MENTIONS]
+    if not names:
+        return []
+    query = {
+        'username': {'$in': [re.compile(f'^{re.escape(n)}$', re.IGNORECASE) for n in names]},
+        '_id': {'$ne': author['_id']},
+        'blocked_users': {'$ne': author['_id']}
+    }
+    if visibility != 'public':
+        query['_id'] = {'$in': _friend_ids(author['_id'])}
+    return list(users_collection.find(query, {'username': 1}))
+
+
+def _notify(user_id, notification_type, actor, comment=None, **extra):
+    """
+    Record a notification for user_id about something actor did. Nothing is sent for your own
+    actions or from people you've blocked. Likes and reactions reuse one notification per actor and comment so toggling
+    them doesn't flood the recipient.
+    """
+    if not user_id or not actor or user_id == actor['_id']:
+        return None
+    if users_collection.count_documents({'_id': user_id, 'blocked_users': actor['_id']}, limit=1):
+        return None
+    doc = {
+        'user_id': user_id,
+        'type': notification_type,
+        'actor_id': actor['_id'],
+        'actor_username': actor['username'],
+        'read': False,
+        'created_at': datetime.utcnow(),
+        **extra
+    }
+    if comment is not None:
+        doc.update({
+            'comment_id': comment['_id'],
+            'content_id': comment.get('content_id'),
+            'content_type': comment.get('content_type')
+        })
+
+    if notification_type in ('comment_like', 'comment_reaction'):
+        key = {k: doc[k] for k in ('user_id', 'type', 'actor_id', 'comment_id')}
+        doc = notifications_collection.find_one_and_update(
+            key, {'$set': doc}, upsert=True, return_document=ReturnDocument.AFTER
+        )
+    else:
+        doc['_id'] = notifications_collection.insert_one(doc).inserted_id
+
+    _push_to_user(str(user_id), 'notification', {
+        'notification': _notification_response(doc, {comment['_id']: comment} if comment else {}),
+        'unread_count': notifications_collection.count_documents({'user_id': user_id, 'read': False})
+    })
+    return doc
+
+
+def _notification_response(notification, comments):
+    comment = comments.get(notification.get('comment_id'))
+    preview = None
+    if comment and not _is_removed(comment):
+        preview = comment['comment_text'][:100]
+    return {
+        'id': str(notification['_id']),
+        'type': notification['type'],
+        'actor': {'id': str(notification['actor_id']), 'username': notification['actor_username']} if notification.get('actor_id') else None,
+        'comment_id': str(notification['comment_id']) if notification.get('comment_id') else None,
+        'comment_preview': preview,
+        'content_id': notification.get('content_id'),
+        'content_type': notification.get('content_type'),
+        'reaction': notification.get('reaction'),
+        'emoji': COMMENT_REACTIONS.get(notification.get('reaction')),
+        'list_id': str(notification['list_id']) if notification.get('list_id') else None,
+        'list_name': notification.get('list_name'),
+        'title': notification.get('title'),
+        'season': notification.get('season'),
+        'episode': notification.get('episode'),
+        'read': notification.get('read', False),
+        'created_at': notification['created_at'].isoformat()
+    }
+
+
+@app.route('/api/notifications', methods=['GET'])
+@jwt_required()
+def get_notifications():
+    """Newest first. ?unread=1 for unread only, ?limit= (max 100), ?before= the previous page's next_cursor"""
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        limit = min(100, max(1, request.args.get('limit', 30, type=int)))
+
+        query = {'user_id': user_id}
+        if request.args.get('unread') in ('1', 'true'):
+            query['read'] = False
+        if request.args.get('before'):
+            try:
+                query['created_at'] = {'$lt': datetime.fromisoformat(request.args['before'])}
+            except ValueError:
+                return jsonify({'error': 'Invalid cursor'}), 400
+
+        notifications = list(notifications_collection.find(query).sort('created_at', -1).limit(limit + 1))
+        next_cursor = None
+        if len(notifications) > limit:
+            notifications = notifications[:limit]
+            next_cursor = notifications[-1]['created_at'].isoformat()
+
+        comment_ids = [n['comment_id'] for n in notifications if n.get('comment_id')]
+        comments = {c['_id']: c for c in comments_collection.find(
+            {'_id': {'$in': comment_ids}}, {'comment_text': 1, 'deleted': 1, 'hidden': 1})}
+
+        return jsonify({
+            'notifications': [_notification_response(n, comments) for n in notifications],
+            'unread_count': notifications_collection.count_documents({'user_id': user_id, 'read': False}),
+            'next_cursor': next_cursor
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+@app.route('/api/notifications/unread-count', methods=['GET'])
+@jwt_required()
+def get_unread_notification_count():
+    try:
+        user_id = ObjectId(get_jwt_identity())
+        return jsonify({'unread_count': notifications_collection.count_documents({'user_id': user_id, 'read': False})}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/friends/requests/<request_id>/accept', methods=['POST'])
+@app.route('/api/notifications/read', methods=['POST'])
 @jwt_required()
-def accept_friend_request(request_id):
+@validate_json(NOTIFICATIONS_READ_SCHEMA)
+def mark_notifications_read():
+    """Mark the given notification ids read, or all of them when ids is omitted"""
     try:
-        user_id = get_jwt_identity()
-
-        friend_request = friend_requests_collection.find_one({
-            '_id': ObjectId(request_id),
-            'to_user_id': ObjectId(user_id),
-            'status': 'pending'
-        })
-
-        if not friend_request:
-            return jsonify({'error': 'Friend request not found'}), 404
-
-        from_user_id = friend_request['from_user_id']
+        user_id = ObjectId(get_jwt_identity())
+        data = request.get_json()
 
-        # Add to friends lists
-        users_collection.update_one(
-            {'_id': ObjectId(user_id)},
-            {'$addToSet': {'friends': from_user_id}}
-        )
-        users_collection.update_one(
-            {'_id': from_user_id},
-            {'$addToSet': {'friends': ObjectId(user_id)}}
-        )
+        query = {'user_id': user_id, 'read': False}
+        if data.get('ids') is not None:
+            query['_id'] = {'$in': [ObjectId(i) for i in data['ids']]}
+        marked = notifications_collection.update_many(query, {'$set': {'read': True, 'read_at': datetime.utcnow()}}).modified_count
+        unread = notifications_collection.count_documents({'user_id': user_id, 'read': False})
 
-        # Update request status
-        friend_requests_collection.update_one(
-            {'_id': ObjectId(request_id)},
-            {'$set': {'status': 'accepted'}}
-        )
+        # Keep the bell in the user's other tabs in step
+        _push_to_user(str(user_id), 'notifications_read', {'unread_count': unread})
 
-        return jsonify({'message': 'Friend request accepted'}), 200
+        return jsonify({'marked_read': marked, 'unread_count': unread}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
+# ============= END AUTHENTICATION & SOCIAL FEATURES =============
 
-@app.route('/api/friends/requests/<request_id>/reject', methods=['POST'])
-@jwt_required()
-def reject_friend_request(request_id):
+# ============= ADMIN ROUTES =============
+
+def _admin_user_summary(user):
+    return {
+        'id': str(user['_id']),
+        'username': user['username'],
+        'email': user.get('email'),
+        'email_verified': user.get('email_verified', False),
+        'role': _user_role(user),
+        'status': user.get('status', 'active'),
+        'suspended_until': user['suspended_until'].isoformat() if user.get('suspended_until') else None,
+        'status_reason': user.get('status_reason'),
+        'two_factor_enabled': user.get('totp_enabled', False),
+        'created_at': user['created_at'].isoformat() if user.get('created_at') else None
+    }
+
+
+def _find_target_user(user_id):
     try:
-        user_id = get_jwt_identity()
+        return users_collection.find_one({'_id': ObjectId(user_id)})
+    except Exception:
+        return None
 
-        friend_request = friend_requests_collection.find_one({
-            '_id': ObjectId(request_id),
-            'to_user_id': ObjectId(user_id),
-            'status': 'pending'
-        })
 
-        if not friend_request:
-            return jsonify({'error': 'Friend request not found'}), 404
+def _can_manage(target):
+    """Staff may only act on accounts with a lower role than their own"""
+    return ROLE_LEVELS.get(_user_role(g.current_user), 0) > ROLE_LEVELS.get(_user_role(target), 0)
 
-        # Update request status
-        friend_requests_collection.update_one(
-            {'_id': ObjectId(request_id)},
-            {'$set': {'status': 'rejected'}}
-        )
 
-        return jsonify({'message': 'Friend request rejected'}), 200
+def _can_moderate(comment):
+    """Moderation follows the same rule as account actions: only comments by lower-ranked authors"""
+    if not comment.get('user_id'):
+        return True  # author deleted their account
+    author = users_collection.find_one({'_id': comment['user_id']}, {'role': 1})
+    return not author or _can_manage(author)
 
-    except Exception as e:
-        return jsonify({'error': str(e)}), 500
 
+def _end_all_sessions(user_id):
+    _revoke_sessions({'user_id': user_id})
+    users_collection.update_one({'_id': user_id}, {'$set': {'tokens_valid_after': datetime.utcnow()}})
+    # Also catches sockets signed in with tokens from before sessions existed
+    _disconnect_sockets(user_id=str(user_id))
 
-@app.route('/api/friends/<friend_id>', methods=['DELETE'])
+
+@app.route('/api/admin/users', methods=['GET'])
 @jwt_required()
-def remove_friend(friend_id):
+@role_required('moderator')
+def admin_list_users():
+    """?q= matches username or email; ?role= and ?status= filter; paginated with page/per_page"""
     try:
-        user_id = get_jwt_identity()
-
-        # Remove from both users' friend lists
-        users_collection.update_one(
-            {'_id': ObjectId(user_id)},
-            {'$pull': {'friends': ObjectId(friend_id)}}
-        )
-        users_collection.update_one(
-            {'_id': ObjectId(friend_id)},
-            {'$pull': {'friends': ObjectId(user_id)}}
-        )
+        query = {}
+        q = request.args.get('q', '').strip()
+        if q:
+            pattern = {'$regex': re.escape(q), '$options': 'i'}
+            query['$or'] = [{'username': pattern}, {'email': pattern}]
+        role = request.args.get('role')
+        if role in USER_ROLES:
+            query['role'] = role if role != 'user' else {'$in': ['user', None]}
+        status = request.args.get('status')
+        if status in ('active', 'suspended', 'banned'):
+            query['status'] = status if status != 'active' else {'$in': ['active', None]}
+
+        page = max(1, request.args.get('page', 1, type=int))
+        per_page = min(100, max(1, request.args.get('per_page', 25, type=int)))
+
+        total = users_collection.count_documents(query)
+        users = users_collection.find(query).sort('created_at', -1).skip((page - 1) * per_page).limit(per_page)
 
-        return jsonify({'message': 'Friend removed'}), 200
+        return jsonify({
+            'users': [_admin_user_summary(u) for u in users],
+            'page': page,
+            'per_page': per_page,
+            'total': total
+        }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-# ============= COMMENTS ROUTES =============
-
-@app.route('/api/comments/<content_id>', methods=['GET'])
+@app.route('/api/admin/users/<user_id>', methods=['GET'])
 @jwt_required()
-def get_comments(content_id):
+@role_required('moderator')
+def admin_get_user(user_id):
     try:
-        user_id = get_jwt_identity()
-
-        # Convert content_id to int (it's stored as integer in DB)
-        try:
-            content_id_int = int(content_id)
-        except ValueError:
-            content_id_int = content_id  # Fallback to string if not a number
-
-        # Get current user's friends
-        user = users_collection.find_one({'_id': ObjectId(user_id)})
-
+        user = _find_target_user(user_id)
         if not user:
             return jsonify({'error': 'User not found'}), 404
 
-        # Get friend IDs - ensure it's a list
-        friend_ids = user.get('friends', [])
-        if friend_ids is None:
-            friend_ids = []
-
-        # Ensure all friends are ObjectIds
-        friend_ids = [ObjectId(fid) if not isinstance(fid, ObjectId) else fid for fid in friend_ids]
-
-        # Always include own comments
-        friend_ids.append(ObjectId(user_id))
-
-        print(f"[Comments] Getting comments for content_id: {content_id} (converted to {content_id_int})")
-        print(f"[Comments] User ID: {user_id}")
-        print(f"[Comments] Friend IDs: {friend_ids}")
-
-        # Helper function to recursively get all nested replies
-        def get_nested_replies(parent_id, friend_ids, user_id, depth=0):
-            """Recursively fetch all nested replies for a comment"""
-            replies = list(comments_collection.find({
-                'parent_comment_id': parent_id,
-                'user_id': {'$in': friend_ids}
-            }).sort('created_at', 1))
-
-            print(f"[Comments] {'  ' * depth}Found {len(replies)} replies for parent {parent_id}")
-
-            # Process each reply and get its nested replies
-            for reply in replies:
-                reply_id = reply['_id']
-                print(f"[Comments] {'  ' * depth}Processing reply {reply_id}: {reply['comment_text'][:30]}")
-
-                # Get like count and user's like status
-                reply_like_count = comment_likes_collection.count_documents({'comment_id': reply_id})
-                reply['like_count'] = reply_like_count
-
-                reply_user_liked = comment_likes_collection.find_one({
-                    'comment_id': reply_id,
-                    'user_id': ObjectId(user_id)
-                }) is not None
-                reply['liked_by_user'] = reply_user_liked
-
-                # Recursively get nested replies for this reply
-                nested = get_nested_replies(reply_id, friend_ids, user_id, depth + 1)
-                reply['replies'] = nested
-                print(f"[Comments] {'  ' * depth}Reply {reply_id} has {len(nested)} nested replies")
-
-                # Convert ObjectIds to strings
-                reply['_id'] = str(reply['_id'])
-                reply['user_id'] = str(reply['user_id'])
-                reply['parent_comment_id'] = str(reply['parent_comment_id'])
-                reply['created_at'] = reply['created_at'].isoformat()
-
-            return replies
-
-        # Get comments from user and their friends - use integer content_id
-        # Only get top-level comments (no parent_comment_id, or parent_comment_id is None/null)
-        comments = list(comments_collection.find({
-            'content_id': content_id_int,
-            'user_id': {'$in': friend_ids},
-            '$or': [
-                {'parent_comment_id': {'$exists': False}},
-                {'parent_comment_id': None},
-                {'parent_comment_id': 'None'}  # Handle string "None" from old data
-            ]
-        }).sort('created_at', -1))
-
-        print(f"[Comments] Found {len(comments)} top-level comments")
+        result = _admin_user_summary(user)
+        result['stats'] = {
+            'comments': comments_collection.count_documents({'user_id': user['_id']}),
+            'warnings': user.get('warning_count', 0),
+            'open_reports': comment_reports_collection.count_documents({
+                'status': 'open',
+                'comment_id': {'$in': [c['_id'] for c in comments_collection.find({'user_id': user['_id']}, {'_id': 1})]}
+            }),
+            'ratings': ratings_collection.count_documents({'user_id': user['_id']}),
+            'friends': friend_requests_collection.count_documents(_friendship_filter(user['_id'])),
+            'active_sessions': sessions_collection.count_documents({'user_id': user['_id'], 'revoked': False})
+        }
+        result['recent_actions'] = _json_safe(list(
+            admin_audit_log_collection.find({'target_user_id': user['_id']}, {'_id': 0}).sort('created_at', -1).limit(20)
+        ))
 
-        # For each comment, add like count, user's like status, and replies
-        for comment in comments:
-            comment_id = comment['_id']
+        return jsonify(result), 200
 
-            # Get like count
-            like_count = comment_likes_collection.count_documents({'comment_id': comment_id})
-            comment['like_count'] = like_count
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-            # Check if current user liked this comment
-            user_liked = comment_likes_collection.find_one({
-                'comment_id': comment_id,
-                'user_id': ObjectId(user_id)
-            }) is not None
-            comment['liked_by_user'] = user_liked
 
-            # Get all nested replies recursively
-            comment['replies'] = get_nested_replies(comment_id, friend_ids, user_id)
-            print(f"[Comments] Top-level comment '{comment['comment_text'][:30]}' has {len(comment['replies'])} direct replies")
-            for i, reply in enumerate(comment['replies']):
-                print(f"[Comments]   Reply {i}: '{reply['comment_text'][:30]}' has {len(reply.get('replies', []))} nested replies")
+@app.route('/api/admin/users/<user_id>/suspend', methods=['POST'])
+@jwt_required()
+@role_required('moderator')
+@validate_json(ADMIN_SUSPEND_SCHEMA)
+def admin_suspend_user(user_id):
+    try:
+        data = request.get_json()
+        user = _find_target_user(user_id)
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        if not _can_manage(user):
+            return jsonify({'error': 'You cannot act on this account'}), 403
 
-            comment['_id'] = str(comment['_id'])
-            comment['user_id'] = str(comment['user_id'])
-            comment['created_at'] = comment['created_at'].isoformat()
+        days = data.get('days', 7)
+        until = datetime.utcnow() + timedelta(days=days)
+        users_collection.update_one(
+            {'_id': user['_id']},
+            {'$set': {'status': 'suspended', 'suspended_until': until, 'status_reason': data['reason']}}
+        )
+        _end_all_sessions(user['_id'])
+        _audit('suspend_user', user['_id'], {'reason': data['reason'], 'days': days})
 
-        return jsonify(comments), 200
+        return jsonify({'message': f"{user['username']} suspended until {until.isoformat()}"}), 200
 
     except Exception as e:
-        print(f"[Comments] Error: {str(e)}")
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/comments', methods=['POST'])
+@app.route('/api/admin/users/<user_id>/ban', methods=['POST'])
 @jwt_required()
-def add_comment():
+@role_required('admin')
+@validate_json(ADMIN_BAN_SCHEMA)
+def admin_ban_user(user_id):
     try:
-        user_id = get_jwt_identity()
         data = request.get_json()
+        user = _find_target_user(user_id)
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        if not _can_manage(user):
+            return jsonify({'error': 'You cannot act on this account'}), 403
 
-        user = users_collection.find_one({'_id': ObjectId(user_id)})
+        users_collection.update_one(
+            {'_id': user['_id']},
+            {'$set': {'status': 'banned', 'status_reason': data['reason']}, '$unset': {'suspended_until': ''}}
+        )
+        _end_all_sessions(user['_id'])
+        _audit('ban_user', user['_id'], {'reason': data['reason']})
 
-        comment = {
-            'user_id': ObjectId(user_id),
-            'username': user['username'],
-            'content_id': data['content_id'],
-            'content_type': data['content_type'],
-            'comment_text': data['comment_text'],
-            'created_at': datetime.utcnow()
-        }
+        return jsonify({'message': f"{user['username']} banned"}), 200
 
-        # If this is a reply, add parent_comment_id
-        if data.get('parent_comment_id'):
-            comment['parent_comment_id'] = ObjectId(data['parent_comment_id'])
-            print(f"[Comments] Adding reply to comment {data['parent_comment_id']}")
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-        print(f"[Comments] Adding comment: user_id={comment['user_id']}, content_id={comment['content_id']}, text={comment['comment_text'][:50]}...")
 
-        result = comments_collection.insert_one(comment)
-        comment['_id'] = str(result.inserted_id)
-        comment['user_id'] = str(comment['user_id'])
-        if comment.get('parent_comment_id'):
-            comment['parent_comment_id'] = str(comment['parent_comment_id'])
-        comment['created_at'] = comment['created_at'].isoformat()
+@app.route('/api/admin/users/<user_id>/reinstate', methods=['POST'])
+@jwt_required()
+@role_required('moderator')
+def admin_reinstate_user(user_id):
+    try:
+        user = _find_target_user(user_id)
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        if not _can_manage(user):
+            return jsonify({'error': 'You cannot act on this account'}), 403
+        # Only admins can lift a ban
+        if user.get('status') == 'banned' and _user_role(g.current_user) != 'admin':
+            return jsonify({'error': 'Admin role required'}), 403
 
-        print(f"[Comments] Comment saved with ID: {comment['_id']}")
+        users_collection.update_one(
+            {'_id': user['_id']},
+            {'$set': {'status': 'active'}, '$unset': {'suspended_until': '', 'status_reason': ''}}
+        )
+        _audit('reinstate_user', user['_id'], {'previous_status': user.get('status', 'active')})
 
-        return jsonify(comment), 201
+        return jsonify({'message': f"{user['username']} reinstated"}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/comments/<comment_id>', methods=['DELETE'])
+@app.route('/api/admin/users/<user_id>/reset-password', methods=['POST'])
 @jwt_required()
-def delete_comment(comment_id):
+@role_required('admin')
+def admin_reset_password(user_id):
+    """Invalidate the current password, end all sessions and email the user a reset link"""
     try:
-        user_id = get_jwt_identity()
-
-        result = comments_collection.delete_one({
-            '_id': ObjectId(comment_id),
-            'user_id': ObjectId(user_id)
-        })
-
-        if result.deleted_count == 0:
-            return jsonify({'error': 'Comment not found or unauthorized'}), 404
-
-        # Also delete all likes for this comment
-        comment_likes_collection.delete_many({'comment_id': ObjectId(comment_id)})
+        user = _find_target_user(user_id)
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        if not _can_manage(user):
+            return jsonify({'error': 'You cannot act on this account'}), 403
+        if not _email_base_url():
+            return jsonify({'error': 'APP_BASE_URL must be set before reset links can be emailed'}), 503
 
-        # Also delete all replies to this comment
-        comments_collection.delete_many({'parent_comment_id': ObjectId(comment_id)})
+        users_collection.update_one(
+            {'_id': user['_id']},
+            {'$set': {'password_hash': generate_password_hash(secrets.token_urlsafe(32))}}
+        )
+        _end_all_sessions(user['_id'])
+        _send_password_reset_email(user)
+        _audit('reset_password', user['_id'])
 
-        return jsonify({'message': 'Comment deleted'}), 200
+        return jsonify({'message': f"Password reset link sent to {user['username']}"}), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/comments/<comment_id>/like', methods=['POST'])
+@app.route('/api/admin/users/<user_id>/role', methods=['PUT'])
 @jwt_required()
-def like_comment(comment_id):
+@role_required('admin')
+@validate_json(ADMIN_ROLE_SCHEMA)
+def admin_set_role(user_id):
     try:
-        user_id = get_jwt_identity()
+        data = request.get_json()
+        user = _find_target_user(user_id)
+        if not user:
+            return jsonify({'error': 'User not found'}), 404
+        if user['_id'] == g.current_user['_id']:
+            return jsonify({'error': 'You cannot change your own role'}), 400
+        if not _can_manage(user):
+            return jsonify({'error': "You cannot change this user's role"}), 403
 
-        # Check if already liked
-        existing_like = comment_likes_collection.find_one({
-            'comment_id': ObjectId(comment_id),
-            'user_id': ObjectId(user_id)
-        })
+        users_collection.update_one({'_id': user['_id']}, {'$set': {'role': data['role']}})
+        _audit('set_role', user['_id'], {'from': _user_role(user), 'to': data['role']})
 
-        if existing_like:
-            return jsonify({'error': 'Already liked'}), 400
+        return jsonify({'message': f"{user['username']} is now {data['role']}"}), 200
 
-        # Add like
-        comment_likes_collection.insert_one({
-            'comment_id': ObjectId(comment_id),
-            'user_id': ObjectId(user_id),
-            'created_at': datetime.utcnow()
-        })
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
 
-        # Get new like count
-        like_count = comment_likes_collection.count_documents({'comment_id': ObjectId(comment_id)})
 
-        return jsonify({'like_count': like_count}), 200
+@app.route('/api/admin/stats', methods=['GET'])
+@jwt_required()
+@role_required('admin')
+def admin_stats():
+    try:
+        now = datetime.utcnow()
+        week_ago = now - timedelta(days=7)
+
+        return jsonify({
+            'users': {
+                'total': users_collection.count_documents({}),
+                'new_last_7_days': users_collection.count_documents({'created_at': {'$gte': week_ago}}),
+                'suspended': users_collection.count_documents({'status': 'suspended'}),
+                'banned': users_collection.count_documents({'status': 'banned'}),
+                'by_role': {role: users_collection.count_documents(
+                    {'role': role} if role != 'user' else {'role': {'$in': ['user', None]}}) for role in USER_ROLES}
+            },
+            'active_sessions': sessions_collection.count_documents({'revoked': False, 'expires_at': {'$gt': now}}),
+            'comments': comments_collection.count_documents({}),
+            'comment_likes': comment_likes_collection.count_documents({}),
+            'ratings': ratings_collection.count_documents({}),
+            'pending_friend_requests': friend_requests_collection.count_documents({'status': 'pending'}),
+            'watchparty_rooms': len(watchparty_rooms),
+            'watchparty_users': len(user_rooms)
+        }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
 
-@app.route('/api/comments/<comment_id>/like', methods=['DELETE'])
+@app.route('/api/admin/audit-log', methods=['GET'])
 @jwt_required()
-def unlike_comment(comment_id):
+@role_required('admin')
+def admin_audit_log():
     try:
-        user_id = get_jwt_identity()
-
-        # Remove like
-        result = comment_likes_collection.delete_one({
-            'comment_id': ObjectId(comment_id),
-            'user_id': ObjectId(user_id)
-        })
-
-        if result.deleted_count == 0:
-            return jsonify({'error': 'Like not found'}), 404
+        query = {}
+        if request.args.get('user_id'):
+            query['target_user_id'] = ObjectId(request.args['user_id'])
+        if request.args.get('action'):
+            query['action'] = request.args['action']
 
-        # Get new like count
-        like_count = comment_likes_collection.count_documents({'comment_id': ObjectId(comment_id)})
+        page = max(1, request.args.get('page', 1, type=int))
+        per_page = min(100, max(1, request.args.get('per_page', 50, type=int)))
+        entries = admin_audit_log_collection.find(query).sort('created_at', -1).skip((page - 1) * per_page).limit(per_page)
 
-        return jsonify({'like_count': like_count}), 200
+        return jsonify({
+            'entries': _json_safe(list(entries)),
+            'page': page,
+            'per_page': per_page,
+            'total': admin_audit_log_collection.count_documents(query)
+        }), 200
 
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
-# ============= END AUTHENTICATION & SOCIAL FEATURES =============
+
+def _migration_authorized():
+    """Migration endpoints accept an admin JWT, or the legacy X-Migration-Secret header"""
+    migration_secret = os.environ.get('MIGRATION_SECRET', '')
+    provided_secret = request.headers.get('X-Migration-Secret', '')
+    if migration_secret and hmac.compare_digest(provided_secret, migration_secret):
+        return True
+
+    try:
+        verify_jwt_in_request()
+        user = users_collection.find_one({'_id': ObjectId(get_jwt_identity())})
+    except Exception:
+        return False
+    if user and _user_role(user) == 'admin' and not _account_block_reason(user):
+        g.current_user = user
+        _audit('run_migration', details={'endpoint': request.path})
+        return True
+    return False
+
 
 # ============= DATABASE MIGRATION ENDPOINT (ONE-TIME USE) =============
 
@@ -3937,14 +10155,10 @@ def migrate_to_user_attributes():
     One-time migration endpoint to move watchlist, continue_watching, and favorites
     from separate collections to user document attributes.
 
-    Usage: POST with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
     """
     try:
-        # Check migration secret
-        migration_secret = os.environ.get('MIGRATION_SECRET', '')
-        provided_secret = request.headers.get('X-Migration-Secret', '')
-
-        if not migration_secret or provided_secret != migration_secret:
+        if not _migration_authorized():
             return jsonify({'error': 'Unauthorized'}), 401
 
         # Check if old collections still exist
@@ -4050,6 +10264,214 @@ def migrate_to_user_attributes():
     except Exception as e:
         return jsonify({'error': str(e)}), 500
 
+@app.route('/api/admin/migrate-comment-threads', methods=['POST'])
+def migrate_comment_threads():
+    """
+    Backfill thread_root_id, path and depth on comments created before threads were stored,
+    and normalize legacy "None" parent ids. Safe to run more than once.
+
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    """
+    try:
+        if not _migration_authorized():
+            return jsonify({'error': 'Unauthorized'}), 401
+
+        normalized = comments_collection.update_many(
+            {'parent_comment_id': {'$in': ['None', '']}},
+            {'$set': {'parent_comment_id': None}}
+        ).modified_count
+
+        parents = {c['_id']: c.get('parent_comment_id') for c in comments_collection.find({}, {'parent_comment_id': 1})}
+
+        def ancestors(comment_id):
+            """Root-first ancestor ids, or None when the chain hits a comment that no longer exists"""
+            chain, seen = [], {comment_id}
+            parent = parents.get(comment_id)
+            while parent is not None:
+                if parent not in parents or parent in seen:
+                    return None
+                chain.append(parent)
+                seen.add(parent)
+                parent = parents[parent]
+            return list(reversed(chain))
+
+        updated = 0
+        orphaned = []
+        for comment in comments_collection.find({'thread_root_id': {'$exists': False}}, {'_id': 1}):
+            path = ancestors(comment['_id'])
+            if path is None:
+                orphaned.append(str(comment['_id']))
+                continue
+            comments_collection.update_one({'_id': comment['_id']}, {'$set': {
+                'thread_root_id': path[0] if path else comment['_id'],
+                'path': path,
+                'depth': len(path)
+            }})
+            updated += 1
+
+        return jsonify({
+            'success': True,
+            'normalized_parent_ids': normalized,
+            'comments_updated': updated,
+            'orphaned_comments': orphaned
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+def _migrate_friendships():
+    """
+    Move friendships off the old users.friends arrays. The arrays were the source of truth,
+    so accepted friend_requests left behind by friends who were removed before the upgrade
+    (both users still have arrays but don't list each other) are deleted first. Every pair
+    listed on both sides then gets an accepted friend_request unless one already exists,
+    duplicate accepted requests for the same pair are collapsed, and the arrays are removed.
+    Safe to run more than once.
+    """
+    existing_users = {u['_id'] for u in users_collection.find({}, {'_id': 1})}
+    friends_of = {
+        u['_id']: {ObjectId(f) for f in u.get('friends') or []}
+        for u in users_collection.find({'friends': {'$exists': True}}, {'friends': 1})
+    }
+
+    def listed_by_both(a, b):
+        return b in friends_of.get(a, ()) and a in friends_of.get(b, ())
+
+    # Accepted requests for pairs the arrays say aren't friends any more
+    stale = [
+        fr['_id']
+        for fr in friend_requests_collection.find({'status': 'accepted'}, {'from_user_id': 1, 'to_user_id': 1})
+        if fr['from_user_id'] in friends_of and fr['to_user_id'] in friends_of
+        and not listed_by_both(fr['from_user_id'], fr['to_user_id'])
+    ]
+    if stale:
+        friend_requests_collection.delete_many({'_id': {'$in': stale}})
+
+    created = 0
+    skipped_missing = 0
+    skipped_one_sided = 0
+    for user_id, friend_ids in friends_of.items():
+        for friend_id in friend_ids:
+            if friend_id not in existing_users or friend_id == user_id:
+                skipped_missing += 1
+                continue
+            if friend_id in friends_of and not listed_by_both(user_id, friend_id):
+                skipped_one_sided += 1
+                continue
+            if _are_friends(user_id, friend_id):
+                continue
+            friend_requests_collection.insert_one({
+                'from_user_id': user_id,
+                'to_user_id': friend_id,
+                'status': 'accepted',
+                'created_at': datetime.utcnow(),
+                'accepted_at': datetime.utcnow(),
+                'migrated': True
+            })
+            created += 1
+
+    # One accepted request per pair, whichever direction it was sent
+    seen = set()
+    duplicates = []
+    for fr in friend_requests_collection.find({'status': 'accepted'}, {'from_user_id': 1, 'to_user_id': 1}).sort('created_at', 1):
+        pair = frozenset((fr['from_user_id'], fr['to_user_id']))
+        if pair in seen:
+            duplicates.append(fr['_id'])
+        seen.add(pair)
+    if duplicates:
+        friend_requests_collection.delete_many({'_id': {'$in': duplicates}})
+
+    unset = users_collection.update_many({'friends': {'$exists': True}}, {'$unset': {'friends': ''}}).modified_count
+
+    return {
+        'friendships_created': created,
+        'missing_friends_skipped': skipped_missing,
+        'one_sided_skipped': skipped_one_sided,
+        'stale_removed': len(stale),
+        'duplicates_removed': len(duplicates),
+        'users_updated': unset
+    }
+
+
+@app.route('/api/admin/migrate-friendships', methods=['POST'])
+def migrate_friendships():
+    """
+    Run the friendship migration by hand. It also runs on startup while any user still has
+    a users.friends array, so this is only needed to re-run it.
+
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    """
+    try:
+        if not _migration_authorized():
+            return jsonify({'error': 'Unauthorized'}), 401
+
+        return jsonify({'success': True, **_migrate_friendships()}), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+
+# Friendships still on users.friends arrays are migrated on startup, before anyone relies on them
+try:
+    if users_collection.find_one({'friends': {'$exists': True}}, {'_id': 1}):
+        print(f"[Friends] Migrated friendships on startup: {_migrate_friendships()}")
+except Exception as e:
+    print(f"[Friends] Startup friendship migration failed: {e}")
+
+@app.route('/api/admin/migrate-watchlists', methods=['POST'])
+def migrate_watchlists():
+    """
+    Move every user's embedded users.watchlist into list documents (one per list_name) and
+    remove the array. Users are also migrated lazily the first time they open their lists.
+
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    """
+    try:
+        if not _migration_authorized():
+            return jsonify({'error': 'Unauthorized'}), 401
+
+        users_migrated = 0
+        lists_touched = 0
+        for user in users_collection.find({'watchlist': {'$exists': True}}, {'_id': 1}):
+            lists_touched += _migrate_user_watchlist(user['_id'])
+            users_migrated += 1
+
+        return jsonify({
+            'success': True,
+            'users_migrated': users_migrated,
+            'lists_touched': lists_touched
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
+@app.route('/api/admin/migrate-watch-history', methods=['POST'])
+def migrate_watch_history():
+    """
+    Move every user's users.continue_watching array into the watch history collection and remove
+    the array. Users are also migrated lazily the first time they load their history.
+
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    """
+    try:
+        if not _migration_authorized():
+            return jsonify({'error': 'Unauthorized'}), 401
+
+        users_migrated = 0
+        records = 0
+        for user in users_collection.find({'continue_watching': {'$exists': True}}, {'_id': 1}):
+            records += _migrate_user_continue_watching(user['_id'])
+            users_migrated += 1
+
+        return jsonify({
+            'success': True,
+            'users_migrated': users_migrated,
+            'records_created': records
+        }), 200
+
+    except Exception as e:
+        return jsonify({'error': str(e)}), 500
+
 # ============= END DATABASE MIGRATION =============
 
 @app.route('/api/admin/drop-old-collections', methods=['POST'])
@@ -4058,14 +10480,10 @@ def drop_old_collections():
     Drop the old watchlists, continue_watching, and favorites collections
     after confirming migration was successful.
 
-    Usage: POST with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
+    Usage: POST as an admin, or with header 'X-Migration-Secret' matching MIGRATION_SECRET env var
     """
     try:
-        # Check migration secret
-        migration_secret = os.environ.get('MIGRATION_SECRET', '')
-        provided_secret = request.headers.get('X-Migration-Secret', '')
-
-        if not migration_secret or provided_secret != migration_secret:
+        if not _migration_authorized():
             return jsonify({'error': 'Unauthorized'}), 401
 
         # Get collection stats before dropping